@@ -1,16 +1,35 @@
+use chrono::naive::NaiveDate;
+use rayon::prelude::*;
 use serde::ser::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
 use std::fs::File;
 use std::io::Error as IOError;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
+mod assets;
+mod cache;
+mod check;
+mod config;
+mod feed;
 mod fileutil;
+mod highlight;
+mod images;
+mod math;
+mod redirect;
 mod rendering;
-mod rss;
+mod schedule;
+mod search;
 mod serialize;
+mod sitemap;
 mod theme;
 mod website;
 
+use cache::CacheTracker;
+pub use check::check;
+pub use schedule::schedule;
+pub use theme::theme_folders;
 use serialize::LayoutInfo;
 use theme::{TemplateType, Theme, ThemeError};
 use website::{BlogElement, OrgFile, Project, Website, WebsiteError};
@@ -22,6 +41,18 @@ pub enum InitError {
     IO(IOError),
 }
 
+impl std::fmt::Display for InitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Theme(err) => write!(f, "{}", err),
+            Self::Website(err) => write!(f, "{}", err),
+            Self::IO(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for InitError {}
+
 impl From<ThemeError> for InitError {
     fn from(err: ThemeError) -> Self {
         Self::Theme(err)
@@ -45,11 +76,90 @@ pub enum RenderError {
     Theme(theme::RenderError),
     HTML(rendering::SerializationError),
     IO(IOError),
-    RSS(rss::Error),
+    Feed(feed::Error),
+    JSON(serde_json::Error),
     FileNotFound(String),
-    InvalidImageDependency { file: String, dependency: String },
+    InvalidAssetDependency { file: String, dependency: String },
+    Image { file: String, err: image::ImageError },
+    Build(BuildReport),
+}
+
+/// The errors of all elements that failed to render, grouped by their file
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    failures: BTreeMap<PathBuf, Vec<RenderError>>,
+}
+
+impl BuildReport {
+    /// Record the error of a failed element and return the value otherwise
+    fn add<T>(&mut self, file: &Path, result: Result<T, RenderError>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.failures.entry(file.to_path_buf()).or_default().push(err);
+                None
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl std::fmt::Display for BuildReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.failures.len() {
+            1 => write!(f, "1 file failed to render:")?,
+            n => write!(f, "{} files failed to render:", n)?,
+        }
+        for (file, errors) in self.failures.iter() {
+            for err in errors.iter() {
+                if err.names_file() {
+                    write!(f, "\n  - {}", err)?;
+                } else {
+                    write!(f, "\n  - {}: {}", file.display(), err)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Theme(err) => write!(f, "Unable to render template: {}", err),
+            Self::HTML(err) => write!(f, "{}", err),
+            Self::IO(err) => write!(f, "{}", err),
+            Self::Feed(err) => write!(f, "{}", err),
+            Self::JSON(err) => write!(f, "{}", err),
+            Self::FileNotFound(file) => write!(f, "{}: File is not part of the blog", file),
+            Self::InvalidAssetDependency { file, dependency } => {
+                let kind = assets::AssetKind::of(dependency).name();
+                write!(f, "{}: {} {} does not exist", file, kind, dependency)
+            }
+            Self::Image { file, err } => write!(f, "{}: Unable to convert image: {}", file, err),
+            Self::Build(report) => write!(f, "{}", report),
+        }
+    }
+}
+
+impl RenderError {
+    /// Whether the message already starts with the file the error occurred in
+    fn names_file(&self) -> bool {
+        matches!(
+            self,
+            Self::HTML(_)
+                | Self::FileNotFound(_)
+                | Self::InvalidAssetDependency { .. }
+                | Self::Image { .. }
+        )
+    }
 }
 
+impl std::error::Error for RenderError {}
+
 impl From<theme::RenderError> for RenderError {
     fn from(err: theme::RenderError) -> Self {
         Self::Theme(err)
@@ -68,54 +178,93 @@ impl From<IOError> for RenderError {
     }
 }
 
-impl From<rss::Error> for RenderError {
-    fn from(err: rss::Error) -> Self {
-        Self::RSS(err)
+impl From<serde_json::Error> for RenderError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::JSON(err)
+    }
+}
+
+impl From<feed::Error> for RenderError {
+    fn from(err: feed::Error) -> Self {
+        Self::Feed(err)
     }
 }
 
 pub struct Builder<'a> {
     theme: Theme<'a>,
     website: Website,
+    /// The folder the build is written to. It is unique for each build and replaces
+    /// the output folder once the build is complete
     temp_dir: PathBuf,
     output_path: &'a str,
+    cache: CacheTracker,
+    /// The address of the development server, if the website is built for `silas serve`
+    serve_url: Option<String>,
+    /// The theme assets used by the rendered pages, which are the only ones copied into the output
+    used_assets: Mutex<BTreeSet<String>>,
 }
 
-pub trait Mode: Sized {
+/// The mode is shared between the threads rendering the website, so it has to be `Sync`
+pub trait Mode: Sized + Sync {
     fn create(builder: &Builder) -> Self;
     fn base_url(&self) -> String;
 
     fn include_page(page: &OrgFile) -> Result<bool, website::OrgFileError>;
     fn include_post(post: &OrgFile) -> Result<bool, website::OrgFileError>;
     fn include_project(project: &Project) -> Result<bool, website::ProjectError>;
-    fn include_rss() -> bool;
+    fn include_feeds() -> bool;
+    fn include_sitemap() -> bool;
+
+    /// Whether pages should poll the development server and reload after a rebuild
+    fn live_reload(&self) -> bool {
+        false
+    }
+
+    /// Whether the elements that rendered successfully are kept if others failed
+    fn allow_partial_build() -> bool {
+        false
+    }
+
+    /// Whether tags, aliases and posts that would be written to the same url fail loading
+    /// the website. `silas check` reports them as problems instead
+    fn reject_conflicts() -> bool {
+        true
+    }
 }
 
-pub struct ReleaseMode {}
+pub struct ReleaseMode {
+    base_url: String,
+}
 pub struct PreviewMode {
     path: String,
 }
+pub struct ServeMode {
+    url: String,
+}
 
 impl Mode for ReleaseMode {
-    fn create(_: &Builder) -> Self {
-        Self {}
+    fn create(builder: &Builder) -> Self {
+        Self {
+            base_url: builder.website.config.base_url.clone(),
+        }
     }
 
     fn base_url(&self) -> String {
-        String::from("https://jhuwald.com")
+        self.base_url.clone()
     }
 
     fn include_page(page: &OrgFile) -> Result<bool, website::OrgFileError> {
-        Ok(page.published.is_some())
+        Ok(page.published.is_some() && !page.scheduled)
     }
 
+    /// Scheduled posts are still validated, so that they don't fail once they are published
     fn include_post(post: &OrgFile) -> Result<bool, website::OrgFileError> {
         if post.published.is_none() {
             return Ok(false);
         }
 
         if post.post_type == website::PostType::Mini {
-            return Ok(true);
+            return Ok(!post.scheduled);
         }
 
         if post.from_preamble("summary").is_none() {
@@ -126,21 +275,31 @@ impl Mode for ReleaseMode {
             return Err(website::OrgFileError::MissingRequiredField("subtitle"));
         }
 
-        return Ok(true);
+        return Ok(!post.scheduled);
     }
 
     fn include_project(project: &Project) -> Result<bool, website::ProjectError> {
         Ok(project.published())
     }
 
-    fn include_rss() -> bool {
+    fn include_feeds() -> bool {
+        true
+    }
+
+    fn include_sitemap() -> bool {
         true
     }
 }
 
 impl Mode for PreviewMode {
     fn create(builder: &Builder) -> Self {
-        let path = PathBuf::from(builder.output_path).canonicalize().unwrap();
+        // the output folder doesn't exist before the first build
+        let path = PathBuf::from(builder.output_path);
+        let path = path.canonicalize().unwrap_or_else(|_| {
+            std::env::current_dir()
+                .map(|dir| dir.join(&path))
+                .unwrap_or(path)
+        });
         Self {
             path: path.to_str().unwrap().to_string(),
         }
@@ -156,7 +315,7 @@ impl Mode for PreviewMode {
 
     fn include_post(post: &OrgFile) -> Result<bool, website::OrgFileError> {
         if let Err(err) = ReleaseMode::include_post(post) {
-            println!("Warning: {:?} in {:?}", err, post.path);
+            println!("Warning: {}", err.with_location(&post.path));
         }
         Ok(true)
     }
@@ -165,50 +324,123 @@ impl Mode for PreviewMode {
         Ok(true)
     }
 
-    fn include_rss() -> bool {
+    fn include_feeds() -> bool {
+        false
+    }
+
+    fn include_sitemap() -> bool {
+        false
+    }
+
+    fn allow_partial_build() -> bool {
+        true
+    }
+}
+
+impl Mode for ServeMode {
+    fn create(builder: &Builder) -> Self {
+        Self {
+            url: builder
+                .serve_url
+                .clone()
+                .expect("ServeMode can only be used by a builder created with Builder::serve"),
+        }
+    }
+
+    fn base_url(&self) -> String {
+        self.url.clone()
+    }
+
+    fn include_page(page: &OrgFile) -> Result<bool, website::OrgFileError> {
+        PreviewMode::include_page(page)
+    }
+
+    fn include_post(post: &OrgFile) -> Result<bool, website::OrgFileError> {
+        PreviewMode::include_post(post)
+    }
+
+    fn include_project(project: &Project) -> Result<bool, website::ProjectError> {
+        PreviewMode::include_project(project)
+    }
+
+    fn include_feeds() -> bool {
+        false
+    }
+
+    fn include_sitemap() -> bool {
         false
     }
+
+    fn live_reload(&self) -> bool {
+        true
+    }
+
+    fn allow_partial_build() -> bool {
+        PreviewMode::allow_partial_build()
+    }
+}
+
+impl Drop for Builder<'_> {
+    /// Remove the build folder if it wasn't moved to the output folder, e.g. because the build failed
+    fn drop(&mut self) {
+        if self.temp_dir.exists() {
+            let _ = fs::remove_dir_all(&self.temp_dir);
+        }
+    }
 }
 
 impl<'a> Builder<'a> {
+    /// Posts published after `today` are only included in previews. Defaults to the current date
     pub fn new<TMode: Mode>(
         website_path: &str,
         theme_path: &str,
         output_path: &'a str,
+        today: Option<NaiveDate>,
     ) -> Result<Self, InitError> {
         let theme = Theme::load(theme_path)?;
-        let website = Website::load::<TMode>(website_path)?;
-
-        let mut temp_dir = std::env::temp_dir();
-        temp_dir.push("silas-generated-output");
+        let website = Website::load::<TMode>(website_path, today)?;
 
-        assert!(
-            !temp_dir.is_dir(),
-            "Silas: temporary directory already exists, aborting generation."
-        );
-        fs::create_dir(&temp_dir)?;
+        // the folder is created next to the output folder, so that it can be renamed
+        let temp_dir = fileutil::unique_sibling(Path::new(output_path), "silas-build");
+        fs::create_dir_all(&temp_dir)?;
 
         Ok(Builder {
             theme,
             website,
             temp_dir,
             output_path,
+            cache: CacheTracker::load(output_path),
+            serve_url: None,
+            used_assets: Mutex::new(BTreeSet::new()),
         })
     }
 
-    pub fn copy_generated_files(&self) -> Result<(), IOError> {
-        if fs::metadata(self.output_path).is_ok() {
-            // clear previous result
-            fs::remove_dir_all(self.output_path)?;
-        }
-
-        let res = fileutil::copy_folder_recursively(&self.temp_dir, self.output_path);
-        self.clear_generated_files();
-        res
+    /// Create a builder for the development server, using `url` as the base url for all links
+    pub fn serve(
+        website_path: &str,
+        theme_path: &str,
+        output_path: &'a str,
+        url: String,
+    ) -> Result<Self, InitError> {
+        let mut builder = Self::new::<ServeMode>(website_path, theme_path, output_path, None)?;
+        builder.serve_url = Some(url);
+        Ok(builder)
     }
 
-    pub fn clear_generated_files(&self) {
-        fs::remove_dir_all(&self.temp_dir).unwrap();
+    /// Replace the output folder with the result of the build. Files that didn't change
+    /// since the previous build are taken from the current output folder
+    pub fn copy_generated_files(&self) -> Result<(), IOError> {
+        let output = Path::new(self.output_path);
+        for path in self.cache.outputs() {
+            let dest = self.temp_dir.join(&path);
+            let src = output.join(&path);
+            if !dest.is_file() && src.is_file() {
+                fs::create_dir_all(dest.parent().unwrap())?;
+                fileutil::link_or_copy(&src, &dest)?;
+            }
+        }
+        self.cache.save(&self.temp_dir)?;
+        fileutil::replace_folder(&self.temp_dir, output)
     }
 
     pub fn generate_single_file<TMode: Mode>(&self, file_path: &str) -> Result<(), RenderError> {
@@ -218,95 +450,275 @@ impl<'a> Builder<'a> {
         match self.website.resolve_path(Path::new(file_path)) {
             None => Err(RenderError::FileNotFound(file_path.to_string())),
             Some(post) => {
-                let ser = post.serialize(&self.website, &mode, &layout)?;
-                let file =
-                    File::create(self.temp_dir.to_str().unwrap().to_string() + "/index.html")?;
-                self.render_element(file, TemplateType::Post, &ser)?;
+                let mut ser = post.serialize(&self.website, &mode, &layout, &self.cache)?;
+                self.render_to_folder("", TemplateType::Post, &mut ser)?;
                 Ok(())
             }
         }
     }
 
-    pub fn generate<TMode: Mode>(&self) -> Result<(), RenderError> {
+    /// Render the complete website. The errors of all elements that failed are collected.
+    /// They abort the build unless the mode allows partial builds, in which case they are returned
+    pub fn generate<TMode: Mode>(&self) -> Result<BuildReport, RenderError> {
         let mode = TMode::create(self);
-
-        self.theme.copy_files(self.temp_dir.to_str().unwrap())?;
+        let mut report = BuildReport::default();
 
         let layout = LayoutInfo::new(&self.website, &mode);
-        let mut rss = rss::RSSBuilder::new(&self.website, &mode);
-
-        let mut ser = self.website.serialize(&mode, &layout)?;
-        let file = self.prepare_file(&self.website, &mut ser.folder_out)?;
-        self.render_element(file, TemplateType::Page, &ser)?;
-        rss.insert_file(&ser);
-
-        for page in self.website.pages.values() {
-            let mut ser = page.serialize(&self.website, &mode, &layout)?;
-            rss.insert_file(&ser);
-            let file = self.prepare_file(page, &mut ser.folder_out)?;
-            self.render_element(file, TemplateType::Page, &ser)?;
+        let mut feeds = feed::FeedBuilder::new(&self.website, &mode);
+        let mut search = search::SearchIndex::default();
+
+        let index = self
+            .website
+            .serialize(&mode, &layout, &self.cache)
+            .map_err(RenderError::from)
+            .and_then(|mut ser| self.render_element(&self.website, TemplateType::Page, &mut ser));
+        report.add(&self.website.index.path, index);
+
+        // All elements are rendered in parallel. The results are processed in a fixed order
+        // afterwards, so that the feeds and the report don't depend on the scheduling
+        let pages: Vec<_> = sorted_by_path(&self.website.pages)
+            .into_par_iter()
+            .map(|page| (page, self.render_file(page, TemplateType::Page, &mode, &layout)))
+            .collect();
+
+        let mut projects: Vec<&Project> = self.website.projects.values().collect();
+        projects.sort_by(|lhs, rhs| lhs.id().cmp(rhs.id()));
+        let projects: Vec<_> = projects
+            .par_iter()
+            .map(|project| {
+                let posts: Vec<_> = sorted_by_path(&project.posts)
+                    .into_par_iter()
+                    .map(|post| (post, self.render_file(post, TemplateType::Post, &mode, &layout)))
+                    .collect();
+                (project, self.render_project(project, &mode, &layout), posts)
+            })
+            .collect();
+
+        // pages are not part of the feeds
+        for (page, res) in pages {
+            report.add(&page.path, res);
         }
 
-        for project in self.website.projects.values() {
-            let mut ser = project.serialize(&self.website, &mode, &layout)?;
-            rss.start_project(project.id(), &ser);
-            let file = self.prepare_file(project, &mut ser.folder_out)?;
-            self.render_element(file, TemplateType::Project(project.project_type), &ser)?;
-
-            for post in project.posts.values() {
-                let mut ser = post.serialize(&self.website, &mode, &layout)?;
-                rss.insert_file(&ser);
-                let file = self.prepare_file(post, &mut ser.folder_out)?;
-                self.render_element(file, TemplateType::Post, &ser)?;
+        for (project, ser, posts) in projects {
+            let ser = report.add(&project.index.path, ser);
+            if let Some(ser) = &ser {
+                feeds.start_project(project.slug(), ser);
+            }
+            for (post, res) in posts {
+                if let Some(post) = report.add(&post.path, res) {
+                    search.insert(&post);
+                    if ser.is_some() {
+                        feeds.insert_file(&post);
+                    }
+                }
             }
-            rss.finish_project();
+            feeds.finish_project();
         }
 
-        rss.write_feeds(self.temp_dir.to_str().unwrap())?;
+        if self.theme.has_tag_pages() {
+            let (tag_index, tag_lists) = serialize::serialize_tags(&self.website, &mode, &layout);
+            if !tag_lists.is_empty() {
+                let mut tag_index = tag_index;
+                let res = self.render_to_folder("tags", TemplateType::TagIndex, &mut tag_index);
+                report.add(Path::new("tags"), res);
+            }
+            let tag_lists: Vec<_> = tag_lists
+                .into_par_iter()
+                .map(|mut list| {
+                    let folder = website::tag_url(list.elem.tag);
+                    let res = self.render_to_folder(&folder, TemplateType::TagList, &mut list);
+                    (folder, res)
+                })
+                .collect();
+            for (folder, res) in tag_lists {
+                report.add(Path::new(folder.trim_start_matches('/')), res);
+            }
+        }
+
+        if self.theme.has_template(&TemplateType::Search) {
+            let mut page = serialize::serialize_search(&self.website, &mode, &layout);
+            let res = self.render_to_folder("search", TemplateType::Search, &mut page);
+            report.add(Path::new("search"), res);
+        }
+
+        if !report.is_empty() && !TMode::allow_partial_build() {
+            return Err(RenderError::Build(report));
+        }
+
+        self.copy_theme_files()?;
+
+        if TMode::include_feeds() {
+            for feed in feeds.write_feeds(self.temp_dir.to_str().unwrap())? {
+                self.cache.record_output(&feed);
+            }
+        }
+
+        if TMode::include_sitemap() {
+            let tags = self.theme.has_tag_pages();
+            for file in sitemap::write(&self.website, &mode, &self.temp_dir, tags)? {
+                self.cache.record_output(&file);
+            }
+        }
+
+        // the index is only read by the search page
+        if self.theme.has_template(&TemplateType::Search) {
+            self.cache.record_output(&search.write(&self.temp_dir)?);
+        }
+
+        for file in redirect::write(&self.website, &mode, &self.temp_dir)? {
+            self.cache.record_output(&file);
+        }
+        Ok(report)
+    }
+
+    /// Serialize and render a page or a post
+    fn render_file<'b, TMode: Mode>(
+        &'b self,
+        file: &'b OrgFile,
+        template: TemplateType,
+        mode: &TMode,
+        layout: &'b LayoutInfo,
+    ) -> Result<serialize::SerializedResult<serialize::SerializedPost<'b>>, RenderError> {
+        let mut ser = file.serialize(&self.website, mode, layout, &self.cache)?;
+        self.render_element(file, template, &mut ser)?;
+        Ok(ser)
+    }
+
+    fn render_project<'b, TMode: Mode>(
+        &'b self,
+        project: &'b Project,
+        mode: &TMode,
+        layout: &'b LayoutInfo,
+    ) -> Result<serialize::SerializedResult<serialize::SerializedProjectIndex<'b>>, RenderError>
+    {
+        let mut ser = project.serialize(&self.website, mode, layout, &self.cache)?;
+        self.render_element(project, TemplateType::Project(project.project_type), &mut ser)?;
+        Ok(ser)
+    }
+
+    /// Copy the theme assets used by the rendered pages
+    fn copy_theme_files(&self) -> Result<(), IOError> {
+        let used = self.used_assets.lock().unwrap();
+        for (src, relative_path) in self.theme.asset_files(&used)? {
+            if self.cache.needs_write(
+                self.output_path,
+                &relative_path,
+                cache::file_fingerprint(&src)?,
+            ) {
+                let dest = self.temp_dir.join(&relative_path);
+                fs::create_dir_all(dest.parent().unwrap())?;
+                fs::copy(&src, &dest)?;
+            }
+        }
         Ok(())
     }
 
-    fn render_element<T: Serialize>(
+    fn render_element<TElem: BlogElement, T: Serialize>(
         &self,
-        mut file: File,
+        elem: &TElem,
         template: TemplateType,
-        elem: &serialize::SerializedResult<T>,
+        ser: &mut serialize::SerializedResult<T>,
     ) -> Result<(), RenderError> {
-        for img in elem.image_deps.iter() {
+        let folder = elem.url(&self.website, String::new());
+        self.render_to_folder(&folder, template, ser)
+    }
+
+    /// Render an element into `folder` (relative to the output folder).
+    /// Only the files that changed since the last build are written
+    fn render_to_folder<T: Serialize>(
+        &self,
+        folder: &str,
+        template: TemplateType,
+        elem: &mut serialize::SerializedResult<T>,
+    ) -> Result<(), RenderError> {
+        let folder = folder.trim_start_matches('/');
+        let folder_out = self.temp_dir.join(folder);
+        elem.folder_out = folder_out.to_str().unwrap().to_string();
+
+        for asset in elem.asset_deps.iter() {
             let mut path = PathBuf::from(&elem.folder_in);
-            path.push(img);
+            path.push(asset);
             if !path.is_file() {
-                return Err(RenderError::InvalidImageDependency {
+                return Err(RenderError::InvalidAssetDependency {
                     file: elem.url.clone(),
                     dependency: path.to_str().unwrap().to_string(),
                 });
             }
-            fs::copy(
-                elem.folder_in.clone() + "/" + img,
-                elem.folder_out.clone() + "/" + img,
-            )?;
+            let relative_path = Path::new(folder).join(asset);
+            if self.cache.needs_write(
+                self.output_path,
+                relative_path.to_str().unwrap(),
+                cache::hash_file(&path)?,
+            ) {
+                let dest = self.temp_dir.join(&relative_path);
+                fs::create_dir_all(dest.parent().unwrap())?;
+                fs::copy(&path, &dest)?;
+            }
         }
-        self.theme.render(&mut file, template, &elem.elem)?;
+
+        for img in elem.images.iter() {
+            self.write_image_variants(folder, &elem.folder_in, img)
+                .map_err(|err| RenderError::Image {
+                    file: elem.url.clone(),
+                    err,
+                })?;
+        }
+
+        let relative_path = Path::new(folder).join("index.html");
+        let fingerprint = self.theme.fingerprint(&template, &elem.elem)?;
+        let relative_path = relative_path.to_str().unwrap();
+        let assets = if self.cache.needs_write(self.output_path, relative_path, fingerprint) {
+            fs::create_dir_all(&folder_out)?;
+            let mut file = File::create(self.temp_dir.join(relative_path))?;
+            self.theme.render(&mut file, template, &elem.elem)?
+        } else {
+            // the assets of unchanged pages are known from the previous build
+            self.cache.previous_assets(relative_path)
+        };
+        self.cache.record_assets(relative_path, &assets);
+        self.used_assets.lock().unwrap().extend(assets);
         Ok(())
     }
 
-    fn prepare_file<T: BlogElement>(
+    /// Write the resized and converted variants of an image. Only the variants that changed
+    /// since the last build are generated, so the image is only decoded if necessary
+    fn write_image_variants(
         &self,
-        elem: &T,
-        folder_out: &mut String,
-    ) -> Result<File, IOError> {
-        let filename = self.temp_dir.to_str().unwrap().to_string();
-        let filename = elem.url(&self.website, filename);
-        fs::create_dir_all(&filename)?;
-        *folder_out = filename.clone();
-        let filename = filename + "/index.html";
-        Ok(File::create(&filename)?)
+        folder: &str,
+        folder_in: &str,
+        img: &images::ResponsiveImage,
+    ) -> Result<(), image::ImageError> {
+        let src = Path::new(folder_in).join(&img.src);
+        let source_hash = cache::hash_file(&src)?;
+        let mut decoded = None;
+        for variant in img.variants() {
+            let relative_path = Path::new(folder).join(&variant.path);
+            if self.cache.needs_write(
+                self.output_path,
+                relative_path.to_str().unwrap(),
+                variant.fingerprint(source_hash),
+            ) {
+                if decoded.is_none() {
+                    decoded = Some(image::open(&src)?);
+                }
+                let dest = self.temp_dir.join(&relative_path);
+                fs::create_dir_all(dest.parent().unwrap())?;
+                variant.write(decoded.as_ref().unwrap(), &dest)?;
+            }
+        }
+        Ok(())
     }
 }
 
+fn sorted_by_path(files: &HashMap<PathBuf, OrgFile>) -> Vec<&OrgFile> {
+    let mut files: Vec<&OrgFile> = files.values().collect();
+    files.sort_by(|lhs, rhs| lhs.path.cmp(&rhs.path));
+    files
+}
+
 #[test]
 fn test_release_mode() -> Result<(), WebsiteError>{
-    let website = Website::load::<ReleaseMode>("testsite")?;
+    let website = Website::load::<ReleaseMode>("testsite", None)?;
     assert!(website.page_by_id("unpublished").is_none());
 
     Ok(())
@@ -314,7 +726,7 @@ fn test_release_mode() -> Result<(), WebsiteError>{
 
 #[test]
 fn test_preview_mode() -> Result<(), WebsiteError>{
-    let website = Website::load::<PreviewMode>("testsite")?;
+    let website = Website::load::<PreviewMode>("testsite", None)?;
 
     let unpub = website.page_by_id("unpublished");
     assert!(unpub.is_some());
@@ -323,3 +735,17 @@ fn test_preview_mode() -> Result<(), WebsiteError>{
 
     Ok(())
 }
+
+#[test]
+fn test_scheduled_project() -> Result<(), WebsiteError> {
+    let before = NaiveDate::from_ymd_opt(2030, 12, 31);
+    let website = Website::load::<ReleaseMode>("testsite", before)?;
+    assert!(!website.projects.contains_key("scheduled"));
+    let scheduled = schedule("testsite", before)?;
+    assert!(scheduled.iter().any(|file| file.path.ends_with("scheduled/index.org")));
+
+    let website = Website::load::<ReleaseMode>("testsite", NaiveDate::from_ymd_opt(2031, 1, 1))?;
+    assert!(website.projects["scheduled"].published());
+
+    Ok(())
+}
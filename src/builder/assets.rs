@@ -0,0 +1,70 @@
+use std::path::Path;
+
+/// How a file linked from an org file is embedded into the page.
+/// Every linked file is copied next to the rendered page
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AssetKind {
+    /// Rendered as `<img>`. Responsive images also get resized and converted variants
+    Image { responsive: bool },
+    /// Rendered as `<video>` with controls
+    Video,
+    /// Rendered as `<audio>` with controls
+    Audio,
+    /// Any other file, rendered as a download link
+    Download,
+}
+
+/// The file endings with special handling. Endings are compared case-insensitively
+const ASSET_KINDS: &[(&str, AssetKind)] = &[
+    ("png", AssetKind::Image { responsive: true }),
+    ("jpg", AssetKind::Image { responsive: true }),
+    ("jpeg", AssetKind::Image { responsive: true }),
+    ("webp", AssetKind::Image { responsive: true }),
+    // resizing would drop the animation of gifs, and svgs scale by themselves
+    ("gif", AssetKind::Image { responsive: false }),
+    ("svg", AssetKind::Image { responsive: false }),
+    ("mp4", AssetKind::Video),
+    ("webm", AssetKind::Video),
+    ("mp3", AssetKind::Audio),
+    ("ogg", AssetKind::Audio),
+];
+
+impl AssetKind {
+    /// The kind of the file at `path`, based on its file ending
+    pub fn of(path: &str) -> Self {
+        let extension = match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some(extension) => extension.to_lowercase(),
+            None => return Self::Download,
+        };
+        ASSET_KINDS
+            .iter()
+            .find(|(ending, _)| *ending == extension)
+            .map_or(Self::Download, |(_, kind)| *kind)
+    }
+
+    /// The name of the kind in messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Image { .. } => "Image",
+            Self::Video => "Video",
+            Self::Audio => "Audio file",
+            Self::Download => "File",
+        }
+    }
+}
+
+#[test]
+fn test_asset_kind() {
+    assert_eq!(
+        AssetKind::of("photo.JPG"),
+        AssetKind::Image { responsive: true }
+    );
+    assert_eq!(
+        AssetKind::of("images/anim.gif"),
+        AssetKind::Image { responsive: false }
+    );
+    assert_eq!(AssetKind::of("talk.webm"), AssetKind::Video);
+    assert_eq!(AssetKind::of("episode.mp3"), AssetKind::Audio);
+    assert_eq!(AssetKind::of("paper.pdf"), AssetKind::Download);
+    assert_eq!(AssetKind::of("Makefile"), AssetKind::Download);
+}
@@ -0,0 +1,187 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::Error as IOError;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use super::rendering::RenderResult;
+use super::website::{BlogElement, Website};
+use super::Mode;
+
+/// The name of the cache file, which is stored in the output folder
+pub const CACHE_FILE_NAME: &str = ".silas-cache";
+/// Has to be incremented whenever the format of the cache or the rendered html changes
+const CACHE_VERSION: u32 = 12;
+
+#[derive(Serialize, Deserialize, Default)]
+struct BuildCache {
+    version: u32,
+    /// The rendered org files by their path
+    renders: HashMap<PathBuf, CachedRender>,
+    /// The fingerprints of all written files by their path relative to the output folder
+    outputs: HashMap<String, u64>,
+    /// The theme assets used by each page, by its path relative to the output folder
+    assets: HashMap<String, Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CachedRender {
+    /// The hash of the org file contents and the base url
+    hash: u64,
+    result: RenderResult,
+}
+
+/// Compares the inputs of the current build to the ones of the previous build
+/// to skip rendering and writing everything that didn't change
+pub struct CacheTracker {
+    previous: Option<BuildCache>,
+    current: Mutex<BuildCache>,
+}
+
+pub fn hash<T: Hash>(value: T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Hash the contents of a file
+pub fn hash_file<P: AsRef<Path>>(path: P) -> Result<u64, IOError> {
+    Ok(hash(fs::read(path)?))
+}
+
+/// A cheap fingerprint for files that are unlikely to change, based only on their metadata
+pub fn file_fingerprint<P: AsRef<Path>>(path: P) -> Result<u64, IOError> {
+    let metadata = fs::metadata(path)?;
+    Ok(hash((metadata.len(), metadata.modified()?)))
+}
+
+impl CacheTracker {
+    pub fn load(output_path: &str) -> Self {
+        let previous = fs::read_to_string(Path::new(output_path).join(CACHE_FILE_NAME))
+            .ok()
+            .and_then(|contents| serde_json::from_str::<BuildCache>(&contents).ok())
+            .filter(|cache| cache.version == CACHE_VERSION);
+
+        CacheTracker {
+            previous,
+            current: Mutex::new(BuildCache {
+                version: CACHE_VERSION,
+                ..BuildCache::default()
+            }),
+        }
+    }
+
+    /// Returns the html of the previous build if neither the file nor the urls of its link targets changed
+    pub fn lookup_render<T: Mode>(
+        &self,
+        path: &Path,
+        hash: u64,
+        website: &Website,
+        mode: &T,
+    ) -> Option<RenderResult> {
+        let cached = self.previous.as_ref()?.renders.get(path)?;
+        if cached.hash != hash {
+            return None;
+        }
+
+        // the size of the images is part of the html
+        let folder = path.parent().unwrap_or_else(|| Path::new(""));
+        if !cached.result.images.iter().all(|image| image.is_current(folder)) {
+            return None;
+        }
+
+        for (target, url) in cached.result.link_deps.iter() {
+            // links to headlines also depend on the anchor still existing in the target
+            let (url, anchor) = match url.find('#') {
+                Some(index) => (&url[..index], Some(&url[index + 1..])),
+                None => (url.as_str(), None),
+            };
+            match website.resolve_path(target) {
+                Some(file) if file.url(website, mode.base_url()) == url => {
+                    if let Some(anchor) = anchor {
+                        if !file.anchors().iter().any(|a| a.id == anchor) {
+                            return None;
+                        }
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        self.current
+            .lock()
+            .unwrap()
+            .renders
+            .insert(path.to_path_buf(), cached.clone());
+        Some(cached.result.clone())
+    }
+
+    pub fn store_render(&self, path: &Path, hash: u64, result: &RenderResult) {
+        self.current.lock().unwrap().renders.insert(
+            path.to_path_buf(),
+            CachedRender {
+                hash,
+                result: result.clone(),
+            },
+        );
+    }
+
+    /// Register an output file and return true if it has to be written.
+    /// This is the case if its fingerprint changed or it is missing from the output folder
+    pub fn needs_write(&self, output_path: &str, relative_path: &str, fingerprint: u64) -> bool {
+        self.current
+            .lock()
+            .unwrap()
+            .outputs
+            .insert(relative_path.to_string(), fingerprint);
+
+        match &self.previous {
+            None => true,
+            Some(previous) => {
+                previous.outputs.get(relative_path) != Some(&fingerprint)
+                    || !Path::new(output_path).join(relative_path).is_file()
+            }
+        }
+    }
+
+    /// Register an output file that is written on every build
+    pub fn record_output(&self, relative_path: &str) {
+        self.current
+            .lock()
+            .unwrap()
+            .outputs
+            .insert(relative_path.to_string(), 0);
+    }
+
+    /// Register the theme assets used by a page
+    pub fn record_assets(&self, relative_path: &str, assets: &[String]) {
+        self.current
+            .lock()
+            .unwrap()
+            .assets
+            .insert(relative_path.to_string(), assets.to_vec());
+    }
+
+    /// The theme assets a page used in the previous build
+    pub fn previous_assets(&self, relative_path: &str) -> Vec<String> {
+        self.previous
+            .as_ref()
+            .and_then(|previous| previous.assets.get(relative_path))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// All files that are part of the current build
+    pub fn outputs(&self) -> Vec<String> {
+        self.current.lock().unwrap().outputs.keys().cloned().collect()
+    }
+
+    pub fn save(&self, folder: &Path) -> Result<(), IOError> {
+        let contents = serde_json::to_string(&*self.current.lock().unwrap())?;
+        fs::write(folder.join(CACHE_FILE_NAME), contents)
+    }
+}
@@ -0,0 +1,188 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use super::assets::AssetKind;
+use super::rendering::OrgHTMLHandler;
+use super::website::{OrgFile, OrgFileError, Project, ProjectError, Website};
+use super::{Builder, Mode, ReleaseMode};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProblemKind {
+    /// The website couldn't be loaded, so no further checks were done
+    LoadFailed,
+    RenderFailed,
+    BrokenLink,
+    /// Links without type, to sections or with an unknown type
+    UnsupportedLink,
+    InvalidUrl,
+    MissingAsset,
+    MissingField,
+    UnknownAttribute,
+    InvalidAttribute,
+    DuplicateId,
+    /// Tags without a url or with the same url as another tag
+    InvalidTag,
+}
+
+#[derive(Serialize)]
+pub struct Problem {
+    pub file: String,
+    pub kind: ProblemKind,
+    pub message: String,
+}
+
+/// All problems found by `silas check`
+#[derive(Serialize, Default)]
+pub struct CheckReport {
+    pub problems: Vec<Problem>,
+}
+
+/// Includes every element like the preview, but without printing warnings.
+/// They are collected in the report instead
+pub struct CheckMode;
+
+impl Mode for CheckMode {
+    fn create(_: &Builder) -> Self {
+        CheckMode
+    }
+
+    fn base_url(&self) -> String {
+        String::new()
+    }
+
+    fn include_page(_: &OrgFile) -> Result<bool, OrgFileError> {
+        Ok(true)
+    }
+
+    fn include_post(_: &OrgFile) -> Result<bool, OrgFileError> {
+        Ok(true)
+    }
+
+    fn include_project(_: &Project) -> Result<bool, ProjectError> {
+        Ok(true)
+    }
+
+    fn include_feeds() -> bool {
+        false
+    }
+
+    fn include_sitemap() -> bool {
+        false
+    }
+
+    fn reject_conflicts() -> bool {
+        false
+    }
+}
+
+impl CheckReport {
+    fn add<P: AsRef<Path>>(&mut self, file: P, kind: ProblemKind, message: String) {
+        self.problems.push(Problem {
+            file: file.as_ref().to_str().unwrap().to_string(),
+            kind,
+            message,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn print(&self) {
+        for problem in self.problems.iter() {
+            println!("{}: {}", problem.file, problem.message);
+        }
+        match self.problems.len() {
+            0 => println!("No problems found"),
+            1 => println!("Found 1 problem"),
+            n => println!("Found {} problems", n),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    fn check_file(&mut self, website: &Website, file: &OrgFile, validate_urls: bool) {
+        if let Err(err) = file.check_required_fields() {
+            self.add(&file.path, ProblemKind::MissingField, err.to_string());
+        }
+
+        let (result, problems) = match OrgHTMLHandler::check_post(website, file, &CheckMode) {
+            Ok(res) => res,
+            Err(err) => {
+                self.add(&file.path, ProblemKind::RenderFailed, err.to_string());
+                return;
+            }
+        };
+
+        for (kind, message) in problems {
+            if kind != ProblemKind::InvalidUrl || validate_urls {
+                self.add(&file.path, kind, message);
+            }
+        }
+
+        let folder = file.path.parent().unwrap();
+        for asset in result.asset_deps {
+            if !folder.join(&asset).is_file() {
+                let kind = AssetKind::of(&asset).name();
+                self.add(
+                    &file.path,
+                    ProblemKind::MissingAsset,
+                    format!("{} {} does not exist", kind, asset),
+                );
+            }
+        }
+    }
+}
+
+/// Load the website at `website_path`, render every org file without writing
+/// any output and report all problems that were found
+pub fn check(website_path: &str, validate_urls: bool) -> CheckReport {
+    let mut report = CheckReport::default();
+    let website = match Website::load::<CheckMode>(website_path, None) {
+        Ok(website) => website,
+        Err(err) => {
+            report.add(website_path, ProblemKind::LoadFailed, err.to_string());
+            return report;
+        }
+    };
+
+    let mut files: Vec<&OrgFile> = vec![&website.index];
+    files.extend(website.pages.values());
+    for project in website.projects.values() {
+        files.push(&project.index);
+        files.extend(project.posts.values());
+        for post in project.posts.values() {
+            match ReleaseMode::include_post(post) {
+                Err(OrgFileError::MissingRequiredField(field)) => report.add(
+                    &post.path,
+                    ProblemKind::MissingField,
+                    format!("Missing {}, which is required for published posts", field),
+                ),
+                Err(err) => report.add(&post.path, ProblemKind::MissingField, err.to_string()),
+                Ok(_) => {}
+            }
+        }
+    }
+    files.sort_by(|lhs, rhs| lhs.path.cmp(&rhs.path));
+
+    for file in files {
+        report.check_file(&website, file, validate_urls);
+    }
+    for (path, err) in website.url_errors() {
+        report.add(path, ProblemKind::DuplicateId, err.to_string());
+    }
+    for (path, err) in website.alias_errors() {
+        report.add(path, ProblemKind::DuplicateId, err.to_string());
+    }
+    for (path, err) in website.tag_errors() {
+        report.add(path, ProblemKind::InvalidTag, err.to_string());
+    }
+    report
+        .problems
+        .sort_by(|lhs, rhs| lhs.file.cmp(&rhs.file));
+    report
+}
@@ -0,0 +1,259 @@
+use std::fs;
+use std::io::Error as IOError;
+use std::path::Path;
+
+use chrono::FixedOffset;
+use serde::Deserialize;
+
+use super::feed::FeedFormat;
+use super::images::ImageFormat;
+use super::redirect::RedirectFormat;
+
+/// The name of the configuration file in the root directory of the blog folder
+pub const CONFIG_FILE_NAME: &str = "silas.toml";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IO(IOError),
+    Parse(toml::de::Error),
+    InvalidTimezone(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::IO(err) => write!(f, "{}", err),
+            Self::Parse(err) => write!(f, "{}", err),
+            Self::InvalidTimezone(timezone) => write!(
+                f,
+                "Invalid timezone `{}`. Expected an utc offset like +02:00",
+                timezone
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<IOError> for ConfigError {
+    fn from(err: IOError) -> Self {
+        Self::IO(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        Self::Parse(err)
+    }
+}
+
+/// Site wide settings that are read from `silas.toml`.
+/// Every value is optional, so a blog folder without a config file is still valid
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteConfig {
+    /// The url the website is deployed to, used as the base for all links in release mode
+    pub base_url: String,
+    pub author: AuthorConfig,
+    /// The format for the title of each page. `{title}` and `{author}` are replaced
+    /// with the page title and the author name respectively
+    title_format: Option<String>,
+    /// The utc offset (e.g. `+02:00`) of the dates in the preamble of the org files. Defaults to utc
+    timezone: Option<String>,
+    pub feed: FeedConfig,
+    pub sitemap: SitemapConfig,
+    pub redirects: RedirectConfig,
+    pub images: ImageConfig,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthorConfig {
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedConfig {
+    /// Defaults to the author email
+    pub managing_editor: Option<String>,
+    /// Defaults to the author email
+    pub webmaster: Option<String>,
+    /// Write an additional feed for each tag to `/tags/<tag>/`
+    pub tags: bool,
+    /// The formats each feed is written in. Defaults to rss only
+    pub formats: Vec<FeedFormat>,
+    /// The maximum number of items in each feed, keeping the most recent ones
+    pub max_items: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SitemapConfig {
+    /// Write a `robots.txt` allowing all crawlers and pointing them to the sitemap
+    pub robots: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedirectConfig {
+    /// The redirect maps written in addition to the redirect pages for `#+aliases`
+    pub formats: Vec<RedirectFormat>,
+}
+
+/// The variants generated for each image. They can be overridden for single images with `#+attr_html`
+#[derive(Deserialize, Hash)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImageConfig {
+    /// The widths the images are resized to. Widths above the width of an image are skipped
+    pub widths: Vec<u32>,
+    /// The formats each size is additionally converted to
+    pub formats: Vec<ImageFormat>,
+    /// The `sizes` attribute of the images, telling the browser how wide they are displayed
+    pub sizes: String,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        ImageConfig {
+            widths: vec![480, 960, 1440],
+            formats: vec![ImageFormat::WebP],
+            sizes: String::from("(max-width: 800px) 100vw, 60vw"),
+        }
+    }
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        FeedConfig {
+            managing_editor: None,
+            webmaster: None,
+            tags: false,
+            formats: vec![FeedFormat::RSS],
+            max_items: None,
+        }
+    }
+}
+
+impl SiteConfig {
+    pub fn load(folder: &Path) -> Result<Self, ConfigError> {
+        let path = folder.join(CONFIG_FILE_NAME);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let mut config: Self = toml::from_str(contents)?;
+        // prevent double slashes when appending paths to the base url
+        while config.base_url.ends_with('/') {
+            config.base_url.pop();
+        }
+        if let Some(timezone) = &config.timezone {
+            if parse_offset(timezone).is_none() {
+                return Err(ConfigError::InvalidTimezone(timezone.clone()));
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn timezone(&self) -> FixedOffset {
+        self.timezone
+            .as_deref()
+            .and_then(parse_offset)
+            .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap())
+    }
+
+    pub fn format_title(&self, title: &str) -> String {
+        let format = match (&self.title_format, &self.author.name) {
+            (Some(format), _) => format.as_str(),
+            (None, Some(_)) => "{title} | {author}",
+            (None, None) => "{title}",
+        };
+
+        format
+            .replace("{title}", title)
+            .replace("{author}", self.author.name.as_deref().unwrap_or(""))
+    }
+
+    /// The author in the format `Name <email>`, as used in the rss feeds
+    pub fn feed_author(&self) -> Option<String> {
+        match (&self.author.name, &self.author.email) {
+            (Some(name), Some(email)) => Some(format!("{} <{}>", name, email)),
+            (Some(name), None) => Some(name.clone()),
+            (None, Some(email)) => Some(email.clone()),
+            (None, None) => None,
+        }
+    }
+
+    pub fn managing_editor(&self) -> Option<String> {
+        self.feed
+            .managing_editor
+            .clone()
+            .or_else(|| self.author.email.clone())
+    }
+
+    pub fn webmaster(&self) -> Option<String> {
+        self.feed
+            .webmaster
+            .clone()
+            .or_else(|| self.author.email.clone())
+    }
+}
+
+/// Parse an utc offset in the format `+HH:MM`, `-HH:MM` or `Z`
+fn parse_offset(offset: &str) -> Option<FixedOffset> {
+    if offset == "Z" || offset == "UTC" {
+        return Some(FixedOffset::east_opt(0).unwrap());
+    }
+
+    let sign = match offset.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let mut parts = offset[1..].splitn(2, ':');
+    let hours = parts.next()?.parse::<i32>().ok()?;
+    let minutes = parts.next().map_or(Some(0), |m| m.parse::<i32>().ok())?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+#[test]
+fn test_site_config() -> Result<(), ConfigError> {
+    let config = SiteConfig::parse(
+        r#"
+        base_url = "https://example.com/"
+        [author]
+        name = "Jane Doe"
+        email = "jane@example.com"
+        "#,
+    )?;
+    assert_eq!(config.base_url, "https://example.com");
+    assert_eq!(config.format_title("Post"), "Post | Jane Doe");
+    assert_eq!(config.managing_editor().as_deref(), Some("jane@example.com"));
+
+    assert_eq!(config.feed.formats, vec![FeedFormat::RSS]);
+    assert_eq!(config.timezone(), FixedOffset::east_opt(0).unwrap());
+
+    let config = SiteConfig::parse(
+        r#"
+        title_format = "{title} - Blog"
+        timezone = "-05:30"
+        [feed]
+        formats = ["atom", "json"]
+        "#,
+    )?;
+    assert_eq!(config.format_title("Post"), "Post - Blog");
+    assert!(config.feed_author().is_none());
+    assert_eq!(config.feed.formats, vec![FeedFormat::Atom, FeedFormat::JSON]);
+    assert_eq!(config.timezone(), FixedOffset::west_opt(5 * 3600 + 1800).unwrap());
+
+    assert!(SiteConfig::parse("timezone = \"Europe/Berlin\"").is_err());
+
+    Ok(())
+}
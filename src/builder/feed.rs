@@ -0,0 +1,396 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io::Error as IOError;
+
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+
+use super::config::SiteConfig;
+use super::serialize::{SerializedPost, SerializedProjectIndex, SerializedResult};
+use super::website;
+use super::website::{BlogElement, Website};
+use super::Mode;
+
+mod atom;
+mod json;
+mod rss;
+
+#[derive(Debug)]
+pub enum Error {
+    RSS(::rss::Error),
+    JSON(serde_json::Error),
+    IO(IOError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::RSS(err) => write!(f, "Unable to write rss feed: {}", err),
+            Self::JSON(err) => write!(f, "Unable to write json feed: {}", err),
+            Self::IO(err) => write!(f, "Unable to write feed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<::rss::Error> for Error {
+    fn from(err: ::rss::Error) -> Self {
+        Self::RSS(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::JSON(err)
+    }
+}
+
+impl From<IOError> for Error {
+    fn from(err: IOError) -> Self {
+        Self::IO(err)
+    }
+}
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    /// RSS 2.0, written to `feed` for backwards compatibility
+    RSS,
+    /// Atom 1.0, written to `atom.xml`
+    Atom,
+    /// JSON Feed 1.1, written to `feed.json`
+    JSON,
+}
+
+impl FeedFormat {
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Self::RSS => "feed",
+            Self::Atom => "atom.xml",
+            Self::JSON => "feed.json",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::RSS => "application/rss+xml",
+            Self::Atom => "application/atom+xml",
+            Self::JSON => "application/feed+json",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::RSS => "RSS",
+            Self::Atom => "Atom",
+            Self::JSON => "JSON Feed",
+        }
+    }
+}
+
+/// The data for a `<link rel="alternate">` element advertising a feed
+#[derive(Serialize)]
+pub struct FeedLink {
+    url: String,
+    title: String,
+    #[serde(rename = "type")]
+    mime_type: &'static str,
+}
+
+impl FeedLink {
+    /// The links to the website feeds in all configured formats
+    pub fn for_website<TMode: Mode>(website: &Website, mode: &TMode) -> Vec<Self> {
+        website
+            .config
+            .feed
+            .formats
+            .iter()
+            .map(|format| FeedLink {
+                url: mode.base_url() + "/" + format.file_name(),
+                title: format!("{} ({})", website.title(), format.name()),
+                mime_type: format.mime_type(),
+            })
+            .collect()
+    }
+}
+
+/// A feed independent of its output format
+pub struct Feed {
+    pub title: String,
+    /// The url of the html page the feed belongs to
+    pub url: String,
+    pub description: String,
+    /// The folder the feed is written to, relative to the output folder
+    folder: String,
+    pub items: Vec<FeedItem>,
+}
+
+#[derive(Clone)]
+pub struct FeedItem {
+    pub title: String,
+    pub url: String,
+    /// The rendered html of the post, with all links and image sources made absolute
+    pub content: String,
+    pub summary: Option<String>,
+    pub published: DateTime<FixedOffset>,
+    pub last_edit: Option<DateTime<FixedOffset>>,
+    pub tags: Vec<String>,
+}
+
+type Post<'a> = SerializedResult<SerializedPost<'a>>;
+
+/// The point in time used for a date from the preamble, which is the start of the day
+/// in the timezone of the website
+fn item_date(date: NaiveDate, timezone: &FixedOffset) -> DateTime<FixedOffset> {
+    timezone
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .unwrap()
+}
+
+impl FeedItem {
+    /// Returns None for posts without publishing date, which are never part of a feed
+    fn new(post: &Post, timezone: &FixedOffset, base_url: &str) -> Option<Self> {
+        Some(FeedItem {
+            title: post.elem.heading.to_string(),
+            url: post.url.clone(),
+            content: absolute_urls(&post.elem.content, &post.url, base_url),
+            summary: post.elem.summary.clone(),
+            published: item_date(post.elem.published?, timezone),
+            last_edit: post.elem.last_edit.map(|date| item_date(date, timezone)),
+            tags: post.elem.tags.iter().map(|tag| tag.title.clone()).collect(),
+        })
+    }
+
+    /// The date of the last change to the item
+    fn updated(&self) -> DateTime<FixedOffset> {
+        self.last_edit.unwrap_or(self.published)
+    }
+}
+
+lazy_static! {
+    static ref URL_ATTRIBUTE: Regex = Regex::new(r#"(src|href|srcset)="([^"]*)""#).unwrap();
+    static ref URL_SCHEME: Regex = Regex::new(r"^[a-zA-Z][a-zA-Z0-9+.-]*:").unwrap();
+}
+
+/// Feed readers display the content outside of the website, so relative urls
+/// (e.g. `./image.png` for images next to the post) have to be resolved against the page url
+fn absolute_urls(html: &str, page_url: &str, base_url: &str) -> String {
+    URL_ATTRIBUTE
+        .replace_all(html, |caps: &Captures| {
+            let url = match &caps[1] {
+                // a list of urls, each followed by a size descriptor
+                "srcset" => caps[2]
+                    .split(',')
+                    .map(|candidate| {
+                        let candidate = candidate.trim();
+                        match candidate.find(' ') {
+                            Some(index) => {
+                                absolute_url(&candidate[..index], page_url, base_url)
+                                    + &candidate[index..]
+                            }
+                            None => absolute_url(candidate, page_url, base_url),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                _ => absolute_url(&caps[2], page_url, base_url),
+            };
+            format!("{}=\"{}\"", &caps[1], url)
+        })
+        .into_owned()
+}
+
+fn absolute_url(url: &str, page_url: &str, base_url: &str) -> String {
+    if url.is_empty() || URL_SCHEME.is_match(url) || url.starts_with("//") {
+        return url.to_string();
+    }
+    if url.starts_with('#') {
+        return page_url.to_string() + url;
+    }
+    if url.starts_with('/') {
+        // an absolute path refers to the root of the host, not to the base url
+        let origin = match base_url.find("://") {
+            Some(start) => match base_url[start + 3..].find('/') {
+                Some(end) => &base_url[..start + 3 + end],
+                None => base_url,
+            },
+            None => base_url,
+        };
+        return origin.to_string() + url;
+    }
+
+    let mut folder = page_url.trim_end_matches('/').to_string();
+    let mut url = url;
+    loop {
+        if let Some(rest) = url.strip_prefix("./") {
+            url = rest;
+        } else if let Some(rest) = url.strip_prefix("../") {
+            if let Some(index) = folder.rfind('/') {
+                folder.truncate(index);
+            }
+            url = rest;
+        } else {
+            break;
+        }
+    }
+    folder + "/" + url
+}
+
+impl Feed {
+    /// The date of the last change to any item. It is used instead of the time of the build,
+    /// so that the feed only changes if its items do. None for empty feeds
+    fn updated(&self) -> Option<DateTime<FixedOffset>> {
+        self.items.iter().map(FeedItem::updated).max()
+    }
+
+    /// Sort the items from newest to oldest and drop everything beyond `max_items`
+    fn sort_and_limit(&mut self, max_items: Option<usize>) {
+        self.items.sort_by(|lhs, rhs| {
+            rhs.published
+                .cmp(&lhs.published)
+                .then_with(|| lhs.url.cmp(&rhs.url))
+        });
+        if let Some(max_items) = max_items {
+            self.items.truncate(max_items);
+        }
+    }
+}
+
+/// Collects the feeds for the website, all projects and (optionally) all tags
+/// and writes them in every configured format
+pub struct FeedBuilder<'a> {
+    config: &'a SiteConfig,
+    base_url: String,
+    current_project: Option<usize>,
+    website: Feed,
+    projects: Vec<Feed>,
+    tags: BTreeMap<String, Feed>,
+    timezone: FixedOffset,
+}
+
+impl<'a> FeedBuilder<'a> {
+    pub fn new<TMode: Mode>(website: &'a Website, mode: &TMode) -> Self {
+        FeedBuilder {
+            config: &website.config,
+            base_url: mode.base_url(),
+            current_project: None,
+            website: Feed {
+                title: website.title().to_string(),
+                url: website.url(&website, mode.base_url()),
+                description: website.description().to_string(),
+                folder: String::new(),
+                items: Vec::new(),
+            },
+            projects: Vec::new(),
+            tags: BTreeMap::new(),
+            timezone: website.config.timezone(),
+        }
+    }
+
+    /// Add a post of the current project to the feeds. Unpublished posts are skipped
+    pub fn insert_file(&mut self, file: &Post) {
+        let index = match self.current_project {
+            Some(index) => index,
+            None => return,
+        };
+        let item = match FeedItem::new(file, &self.timezone, &self.base_url) {
+            Some(item) => item,
+            None => return,
+        };
+
+        if self.config.feed.tags {
+            for tag in file.elem.tags.iter() {
+                self.tag_feed(&tag.title).items.push(item.clone());
+            }
+        }
+        self.projects[index].items.push(item.clone());
+        self.website.items.push(item);
+    }
+
+    fn tag_feed(&mut self, tag: &str) -> &mut Feed {
+        let folder = website::tag_url(tag)[1..].to_string();
+        let base_url = &self.base_url;
+        let config = self.config;
+        self.tags.entry(folder.clone()).or_insert_with(|| Feed {
+            title: config.format_title(tag),
+            url: base_url.clone() + "/" + &folder,
+            description: format!("All posts tagged with {}", tag),
+            folder,
+            items: Vec::new(),
+        })
+    }
+
+    pub fn start_project(&mut self, id: &str, project: &SerializedResult<SerializedProjectIndex>) {
+        self.projects.push(Feed {
+            title: project.elem.title.to_string(),
+            url: project.url.to_string(),
+            description: project.elem.description.to_string(),
+            folder: id.to_string(),
+            items: Vec::new(),
+        });
+        self.current_project = Some(self.projects.len() - 1);
+    }
+
+    pub fn finish_project(&mut self) {
+        self.current_project = None;
+    }
+
+    /// Write all feeds into the folder at `path` and return their paths relative to it
+    pub fn write_feeds(mut self, path: &str) -> Result<Vec<String>, Error> {
+        let mut written = Vec::new();
+        let feeds = std::iter::once(&mut self.website)
+            .chain(self.projects.iter_mut())
+            .chain(self.tags.values_mut());
+
+        for feed in feeds {
+            feed.sort_and_limit(self.config.feed.max_items);
+
+            let folder = if feed.folder.is_empty() {
+                path.to_string()
+            } else {
+                path.to_string() + "/" + &feed.folder
+            };
+            fs::create_dir_all(&folder)?;
+
+            for format in self.config.feed.formats.iter() {
+                let relative_path = if feed.folder.is_empty() {
+                    format.file_name().to_string()
+                } else {
+                    feed.folder.clone() + "/" + format.file_name()
+                };
+                let feed_url = self.base_url.clone() + "/" + &relative_path;
+                let file = File::create(folder.clone() + "/" + format.file_name())?;
+
+                match format {
+                    FeedFormat::RSS => rss::write(feed, self.config, file)?,
+                    FeedFormat::Atom => atom::write(feed, &feed_url, self.config, file)?,
+                    FeedFormat::JSON => json::write(feed, &feed_url, self.config, file)?,
+                }
+                written.push(relative_path);
+            }
+        }
+
+        Ok(written)
+    }
+}
+
+#[test]
+fn test_absolute_urls() {
+    let page = "https://example.com/blog/project/post";
+    let base = "https://example.com/blog";
+    let html = r##"<img src="./image.png"><a href="#fn1">1</a><a href="../other">o</a><a href="/top">t</a><a href="https://a.org">a</a>"##;
+    assert_eq!(
+        absolute_urls(html, page, base),
+        r##"<img src="https://example.com/blog/project/post/image.png"><a href="https://example.com/blog/project/post#fn1">1</a><a href="https://example.com/blog/project/other">o</a><a href="https://example.com/top">t</a><a href="https://a.org">a</a>"##
+    );
+
+    let html = r#"<source srcset="./a-480w.webp 480w, ./a.webp 960w">"#;
+    assert_eq!(
+        absolute_urls(html, page, base),
+        r#"<source srcset="https://example.com/blog/project/post/a-480w.webp 480w, https://example.com/blog/project/post/a.webp 960w">"#
+    );
+}
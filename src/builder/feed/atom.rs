@@ -0,0 +1,70 @@
+use std::io::Write;
+
+use chrono::prelude::*;
+
+use super::super::config::SiteConfig;
+use super::{Error, Feed};
+
+/// Escape text for xml content and attribute values
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn write<W: Write>(
+    feed: &Feed,
+    feed_url: &str,
+    config: &SiteConfig,
+    mut out: W,
+) -> Result<(), Error> {
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"utf-8\"?>")?;
+    writeln!(out, "<feed xmlns=\"http://www.w3.org/2005/Atom\">")?;
+    writeln!(out, "  <title>{}</title>", escape(&feed.title))?;
+    writeln!(out, "  <subtitle>{}</subtitle>", escape(&feed.description))?;
+    writeln!(out, "  <id>{}</id>", escape(&feed.url))?;
+    writeln!(out, "  <link rel=\"alternate\" href=\"{}\"/>", escape(&feed.url))?;
+    writeln!(out, "  <link rel=\"self\" href=\"{}\"/>", escape(feed_url))?;
+    // atom requires the element, so empty feeds use the unix epoch to stay the same between builds
+    let updated = feed
+        .updated()
+        .unwrap_or_else(|| Utc.timestamp_opt(0, 0).unwrap().into());
+    writeln!(out, "  <updated>{}</updated>", updated.to_rfc3339())?;
+
+    // atom requires an author for the feed if not every entry has one
+    writeln!(out, "  <author>")?;
+    writeln!(
+        out,
+        "    <name>{}</name>",
+        escape(config.author.name.as_deref().unwrap_or(&feed.title))
+    )?;
+    if let Some(email) = &config.author.email {
+        writeln!(out, "    <email>{}</email>", escape(email))?;
+    }
+    writeln!(out, "  </author>")?;
+
+    for item in feed.items.iter() {
+        writeln!(out, "  <entry>")?;
+        writeln!(out, "    <title>{}</title>", escape(&item.title))?;
+        writeln!(out, "    <id>{}</id>", escape(&item.url))?;
+        writeln!(out, "    <link rel=\"alternate\" href=\"{}\"/>", escape(&item.url))?;
+        writeln!(out, "    <published>{}</published>", item.published.to_rfc3339())?;
+        writeln!(out, "    <updated>{}</updated>", item.updated().to_rfc3339())?;
+        for tag in item.tags.iter() {
+            writeln!(out, "    <category term=\"{}\"/>", escape(tag))?;
+        }
+        if let Some(summary) = &item.summary {
+            writeln!(out, "    <summary>{}</summary>", escape(summary))?;
+        }
+        writeln!(
+            out,
+            "    <content type=\"html\">{}</content>",
+            escape(&item.content)
+        )?;
+        writeln!(out, "  </entry>")?;
+    }
+
+    writeln!(out, "</feed>")?;
+    Ok(())
+}
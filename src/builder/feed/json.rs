@@ -0,0 +1,75 @@
+use std::io::Write;
+
+use serde::Serialize;
+
+use super::super::config::SiteConfig;
+use super::{Error, Feed};
+
+#[derive(Serialize)]
+struct JSONFeed<'a> {
+    version: &'static str,
+    title: &'a str,
+    home_page_url: &'a str,
+    feed_url: &'a str,
+    description: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    authors: Vec<Author<'a>>,
+    items: Vec<JSONItem<'a>>,
+}
+
+#[derive(Serialize)]
+struct Author<'a> {
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct JSONItem<'a> {
+    id: &'a str,
+    url: &'a str,
+    title: &'a str,
+    content_html: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<&'a str>,
+    date_published: String,
+    date_modified: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<&'a str>,
+}
+
+pub fn write<W: Write>(
+    feed: &Feed,
+    feed_url: &str,
+    config: &SiteConfig,
+    out: W,
+) -> Result<(), Error> {
+    let json = JSONFeed {
+        version: "https://jsonfeed.org/version/1.1",
+        title: &feed.title,
+        home_page_url: &feed.url,
+        feed_url,
+        description: &feed.description,
+        authors: config
+            .author
+            .name
+            .iter()
+            .map(|name| Author { name })
+            .collect(),
+        items: feed
+            .items
+            .iter()
+            .map(|item| JSONItem {
+                id: &item.url,
+                url: &item.url,
+                title: &item.title,
+                content_html: &item.content,
+                summary: item.summary.as_deref(),
+                date_published: item.published.to_rfc3339(),
+                date_modified: item.updated().to_rfc3339(),
+                tags: item.tags.iter().map(|tag| tag.as_str()).collect(),
+            })
+            .collect(),
+    };
+
+    serde_json::to_writer(out, &json)?;
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use std::io::Write;
+
+use rss::{ChannelBuilder, Guid, Item, ItemBuilder};
+
+use super::super::config::SiteConfig;
+use super::{Error, Feed, FeedItem};
+
+fn create_item(item: &FeedItem, config: &SiteConfig) -> Item {
+    let mut builder = ItemBuilder::default();
+    let mut guid = Guid::default();
+    guid.set_value(item.url.to_string());
+    guid.set_permalink(true);
+    builder
+        .title(item.title.to_string())
+        .link(item.url.to_string())
+        .author(config.feed_author())
+        .guid(guid)
+        .content(item.content.to_string())
+        .pub_date(item.published.to_rfc2822());
+
+    if let Some(desc) = &item.summary {
+        builder.description(desc.to_string());
+    };
+    builder.build().unwrap()
+}
+
+pub fn write<W: Write>(
+    feed: &Feed,
+    config: &SiteConfig,
+    out: W,
+) -> Result<(), Error> {
+    let items: Vec<Item> = feed
+        .items
+        .iter()
+        .map(|item| create_item(item, config))
+        .collect();
+
+    let channel = ChannelBuilder::default()
+        .title(feed.title.to_string())
+        .link(feed.url.to_string())
+        .description(feed.description.to_string())
+        .managing_editor(config.managing_editor())
+        .webmaster(config.webmaster())
+        .last_build_date(feed.updated().map(|date| date.to_rfc2822()))
+        .items(items)
+        .build()
+        .unwrap();
+
+    channel.write_to(out)?;
+    Ok(())
+}
@@ -1,44 +1,113 @@
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-pub fn copy_folder_recursively<U: AsRef<Path>, V: AsRef<Path> + std::fmt::Display>(
-    from: U,
-    to: V,
-) -> Result<(), std::io::Error> {
-    if fs::metadata(&to).is_ok() {
-        panic! {"copy_folder_recursively: target '{}' already exists", &to};
-    }
-    let mut stack = Vec::new();
-    stack.push(PathBuf::from(from.as_ref()));
-
-    let output_root = PathBuf::from(to.as_ref());
-    let input_root = PathBuf::from(from.as_ref()).components().count();
-
-    while let Some(working_path) = stack.pop() {
-        // Generate a relative path
-        let src: PathBuf = working_path.components().skip(input_root).collect();
-
-        // Create a destination if missing
-        let dest = if src.components().count() == 0 {
-            output_root.clone()
-        } else {
-            output_root.join(&src)
-        };
-        if fs::metadata(&dest).is_err() {
-            fs::create_dir_all(&dest)?;
-        }
+/// Find all files in `root/folder`. The returned paths are relative to `root`
+pub fn find_files_recursively(root: &Path, folder: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut files = Vec::new();
+    let mut folders = vec![folder.to_path_buf()];
 
-        for entry in fs::read_dir(working_path)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                stack.push(path);
-            } else if let Some(filename) = path.file_name() {
-                let dest_path = dest.join(filename);
-                fs::copy(&path, &dest_path)?;
+    while let Some(folder) = folders.pop() {
+        for entry in fs::read_dir(root.join(&folder))? {
+            let path = folder.join(entry?.file_name());
+            if root.join(&path).is_dir() {
+                folders.push(path);
+            } else {
+                files.push(path);
             }
         }
     }
 
+    Ok(files)
+}
+
+/// Returns a path next to `path` that doesn't exist yet. The name is unique for this process
+/// and starts with a dot, so that web servers don't serve it
+pub fn unique_sibling(path: &Path, suffix: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("output");
+    let parent = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    loop {
+        let candidate = parent.join(format!(
+            ".{}.{}-{}-{}",
+            name,
+            suffix,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        if fs::symlink_metadata(&candidate).is_err() {
+            return candidate;
+        }
+    }
+}
+
+/// Hard link `from` to `to`, or copy it if linking is not possible
+pub fn link_or_copy(from: &Path, to: &Path) -> Result<(), std::io::Error> {
+    if fs::hard_link(from, to).is_err() {
+        fs::copy(from, to)?;
+    }
     Ok(())
 }
+
+/// Replace the folder `target` with `source` in a single step, so that `target` always contains
+/// either the complete previous or the complete new build. The previous contents of `target` are removed.
+/// Fails without touching `target` if the folders can't be exchanged atomically, e.g. if `target` is a mount point
+pub fn replace_folder(source: &Path, target: &Path) -> Result<(), std::io::Error> {
+    if fs::symlink_metadata(target).is_err() {
+        return fs::rename(source, target);
+    }
+
+    exchange(source, target).map_err(|err| {
+        std::io::Error::new(
+            err.kind(),
+            format!("Unable to replace {:?} atomically: {}", target, err),
+        )
+    })?;
+    // `source` now contains the previous build
+    fs::remove_dir_all(source)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn exchange(first: &Path, second: &Path) -> Result<(), std::io::Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let first = CString::new(first.as_os_str().as_bytes())?;
+    let second = CString::new(second.as_os_str().as_bytes())?;
+
+    // glibc only provides a wrapper for renameat2 since 2.28, so the syscall is used directly
+    #[cfg(target_os = "linux")]
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            libc::AT_FDCWD,
+            first.as_ptr(),
+            libc::AT_FDCWD,
+            second.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    #[cfg(target_os = "macos")]
+    let result = unsafe { libc::renamex_np(first.as_ptr(), second.as_ptr(), libc::RENAME_SWAP) };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn exchange(_: &Path, _: &Path) -> Result<(), std::io::Error> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "exchanging folders is not supported on this platform, remove the output folder before building",
+    ))
+}
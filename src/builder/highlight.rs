@@ -0,0 +1,312 @@
+use std::fmt::Write;
+
+use lazy_static::lazy_static;
+use orgize::export::HtmlEscape;
+use syntect::easy::ScopeRangeIterator;
+use syntect::parsing::{
+    ParseState, ParsingError, Scope, ScopeError, ScopeStack, SyntaxReference, SyntaxSet,
+};
+use syntect::util::LinesWithEndings;
+
+lazy_static! {
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+
+    /// Maps textmate scopes to the short class names used by pygments, so that the
+    /// output can be styled by `code.css` from the theme.
+    /// The first matching entry wins, so more specific scopes have to come first
+    static ref CLASSES: Vec<(Scope, &'static str)> = [
+        ("comment.line", "c1"),
+        ("comment.block.documentation", "cd"),
+        ("comment.block", "cm"),
+        ("comment", "c"),
+        ("keyword.operator.word", "ow"),
+        ("keyword.operator", "o"),
+        ("keyword.control.import", "kn"),
+        ("keyword.other.import", "kn"),
+        ("keyword.declaration", "kd"),
+        ("keyword", "k"),
+        ("storage.type", "kt"),
+        ("storage.modifier", "kd"),
+        ("storage", "k"),
+        ("constant.numeric.float", "mf"),
+        ("constant.numeric.hex", "mh"),
+        ("constant.numeric.integer", "mi"),
+        ("constant.numeric.octal", "mo"),
+        ("constant.numeric", "m"),
+        ("constant.character.escape", "se"),
+        ("constant.character", "sc"),
+        ("constant.language", "kc"),
+        ("constant", "no"),
+        ("string.quoted.single", "s1"),
+        ("string.quoted.double", "s2"),
+        ("string.regexp", "sr"),
+        ("string.interpolated", "si"),
+        ("string.unquoted.heredoc", "sh"),
+        ("string.other", "sx"),
+        ("string", "s"),
+        ("entity.name.function", "nf"),
+        ("entity.name.class", "nc"),
+        ("entity.name.struct", "nc"),
+        ("entity.name.enum", "nc"),
+        ("entity.name.trait", "nc"),
+        ("entity.name.type", "nc"),
+        ("entity.other.inherited-class", "nc"),
+        ("entity.name.namespace", "nn"),
+        ("entity.name.module", "nn"),
+        ("entity.name.tag", "nt"),
+        ("entity.name.label", "nl"),
+        ("entity.name.constant", "no"),
+        ("entity.name.decorator", "nd"),
+        ("meta.annotation", "nd"),
+        ("meta.decorator", "nd"),
+        ("entity.other.attribute-name", "na"),
+        ("support.function", "nb"),
+        ("support.type", "nb"),
+        ("support.class", "nc"),
+        ("support.constant", "no"),
+        ("variable.language", "bp"),
+        ("variable.parameter", "nv"),
+        ("variable.other.member", "vi"),
+        ("variable", "nv"),
+        ("invalid", "err"),
+        ("markup.inserted", "gi"),
+        ("markup.deleted", "gd"),
+        ("markup.italic", "ge"),
+        ("markup.bold", "gs"),
+        ("markup.heading", "gh"),
+        ("meta.diff.header", "gh"),
+        ("meta.diff.range", "gu"),
+    ]
+    .iter()
+    .map(|(scope, class)| (Scope::new(scope).unwrap(), *class))
+    .collect();
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Parsing(ParsingError),
+    Scope(ScopeError),
+    Format(std::fmt::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Parsing(err) => write!(f, "{}", err),
+            Self::Scope(err) => write!(f, "{:?}", err),
+            Self::Format(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ParsingError> for Error {
+    fn from(err: ParsingError) -> Self {
+        Self::Parsing(err)
+    }
+}
+
+impl From<ScopeError> for Error {
+    fn from(err: ScopeError) -> Self {
+        Self::Scope(err)
+    }
+}
+
+impl From<std::fmt::Error> for Error {
+    fn from(err: std::fmt::Error) -> Self {
+        Self::Format(err)
+    }
+}
+
+/// How the lines of a source block are numbered
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineNumbers {
+    /// `-n N` starts at N
+    New(usize),
+    /// `+n N` continues the previous numbered block of the file, with N as offset to its last line
+    Continued(usize),
+}
+
+/// Options for a source block, parsed from the switches and header arguments
+/// following the language (e.g. `#+begin_src rust -n 10` or `:number-lines yes`)
+#[derive(Default, Debug, PartialEq)]
+pub struct BlockOptions {
+    /// None if line numbers shouldn't be shown
+    pub line_numbers: Option<LineNumbers>,
+}
+
+impl BlockOptions {
+    pub fn parse(arguments: &str) -> Self {
+        let mut options = Self::default();
+        let mut args = arguments.split_whitespace().peekable();
+
+        while let Some(arg) = args.next() {
+            match arg {
+                "-n" | "+n" => {
+                    let start = args.peek().and_then(|s| s.parse::<usize>().ok());
+                    if start.is_some() {
+                        args.next();
+                    }
+                    let start = start.unwrap_or(1);
+                    options.line_numbers = Some(match arg {
+                        "-n" => LineNumbers::New(start),
+                        _ => LineNumbers::Continued(start),
+                    });
+                }
+                ":number-lines" => {
+                    let value = match args.peek() {
+                        Some(v) if !v.starts_with(':') && !v.starts_with('-') => args.next(),
+                        _ => None,
+                    };
+                    options.line_numbers = match value {
+                        Some("no") | Some("nil") => None,
+                        Some(v) => Some(LineNumbers::New(v.parse::<usize>().unwrap_or(1))),
+                        None => Some(LineNumbers::New(1)),
+                    };
+                }
+                _ => {}
+            }
+        }
+        options
+    }
+
+    /// The number of the first line, if line numbers should be shown. `last_line` is the
+    /// number of the last line of the previous numbered block in the file, or 0 if there is none
+    pub fn first_line(&self, last_line: usize) -> Option<usize> {
+        match self.line_numbers? {
+            LineNumbers::New(start) => Some(start),
+            LineNumbers::Continued(offset) => Some(last_line + offset),
+        }
+    }
+}
+
+/// Find the syntax for an org babel language name
+fn find_syntax(language: &str) -> Option<&'static SyntaxReference> {
+    let token = match language {
+        "emacs-lisp" | "elisp" => "lisp",
+        "shell" | "bash" => "sh",
+        "C" => "c",
+        "C++" => "cpp",
+        lang => lang,
+    };
+    SYNTAX_SET.find_syntax_by_token(token)
+}
+
+fn class_for(stack: &ScopeStack) -> Option<&'static str> {
+    for scope in stack.as_slice().iter().rev() {
+        for (prefix, class) in CLASSES.iter() {
+            if prefix.is_prefix_of(*scope) {
+                return Some(class);
+            }
+        }
+    }
+    None
+}
+
+/// Highlight `code` and return it as html with one span per token.
+/// Unknown languages are rendered as plain text
+pub fn highlight(code: &str, language: &str) -> Result<String, Error> {
+    let syntax = find_syntax(language).unwrap_or_else(|| {
+        if !language.is_empty() {
+            println!(
+                "Warning: No syntax found for source block language `{}`. It will be rendered as plain text",
+                language
+            );
+        }
+        SYNTAX_SET.find_syntax_plain_text()
+    });
+
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut html = String::new();
+    let mut open_class = None;
+
+    for line in LinesWithEndings::from(code) {
+        let ops = state.parse_line(line, &SYNTAX_SET)?;
+        for (range, op) in ScopeRangeIterator::new(&ops, line) {
+            stack.apply(op)?;
+            if range.is_empty() {
+                continue;
+            }
+
+            let class = class_for(&stack);
+            if class != open_class {
+                if open_class.is_some() {
+                    write!(html, "</span>")?;
+                }
+                if let Some(class) = class {
+                    write!(html, "<span class=\"{}\">", class)?;
+                }
+                open_class = class;
+            }
+            write!(html, "{}", HtmlEscape(&line[range]))?;
+        }
+    }
+    if open_class.is_some() {
+        write!(html, "</span>")?;
+    }
+    Ok(html)
+}
+
+/// The number of lines of a source block, as used for line numbers
+pub fn line_count(code: &str) -> usize {
+    code.lines().count().max(1)
+}
+
+/// Render a complete source block, wrapped in the structure expected by `code.css`.
+/// The lines are numbered starting at `first_line`, if it is set
+pub fn render_block(code: &str, language: &str, first_line: Option<usize>) -> Result<String, Error> {
+    let highlighted = highlight(code, language)?;
+
+    Ok(match first_line {
+        None => format!("<div class=\"codeblock\"><pre>{}</pre></div>", highlighted),
+        Some(first) => {
+            let count = line_count(code);
+            let numbers = (first..first + count)
+                .map(|n| n.to_string())
+                .collect::<Vec<String>>()
+                .join("\n");
+            format!(
+                "<div class=\"codeblock\"><table><tr><td class=\"linenos\"><pre>{}</pre></td><td><pre>{}</pre></td></tr></table></div>",
+                numbers, highlighted
+            )
+        }
+    })
+}
+
+/// Render an inline source snippet (`src_lang{code}`)
+pub fn render_inline(code: &str, language: &str) -> Result<String, Error> {
+    Ok(format!(
+        "<code class=\"codeblock\">{}</code>",
+        highlight(code, language)?
+    ))
+}
+
+#[test]
+fn test_block_options() {
+    assert_eq!(BlockOptions::parse("").first_line(0), None);
+    assert_eq!(BlockOptions::parse("-n").first_line(0), Some(1));
+    assert_eq!(BlockOptions::parse("-n 10 :results none").first_line(5), Some(10));
+    assert_eq!(BlockOptions::parse(":number-lines").first_line(0), Some(1));
+    assert_eq!(BlockOptions::parse(":number-lines no").first_line(0), None);
+}
+
+#[test]
+fn test_continued_line_numbers() {
+    assert_eq!(BlockOptions::parse("+n").first_line(0), Some(1));
+    assert_eq!(BlockOptions::parse("+n").first_line(12), Some(13));
+    assert_eq!(BlockOptions::parse("+n 10").first_line(12), Some(22));
+    assert_eq!(
+        BlockOptions::parse("+n 3").line_numbers,
+        Some(LineNumbers::Continued(3))
+    );
+}
+
+#[test]
+fn test_highlight() -> Result<(), Error> {
+    let html = highlight("// comment\nlet x = \"a<b\";\n", "rust")?;
+    assert!(html.contains("<span class=\"c1\">// comment\n</span>"));
+    assert!(html.contains("a&lt;b"));
+    Ok(())
+}
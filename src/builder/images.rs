@@ -0,0 +1,341 @@
+use std::fs::File;
+use std::io::{BufWriter, Error as IOError, Write};
+use std::path::Path;
+
+use image::codecs::avif::AvifEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageError};
+use orgize::export::HtmlEscape;
+use serde::{Deserialize, Serialize};
+
+use super::config::ImageConfig;
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Hash, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    /// Lossless WebP
+    WebP,
+    /// AVIF, which is considerably slower to encode
+    Avif,
+}
+
+impl ImageFormat {
+    fn parse(string: &str) -> Option<Self> {
+        match string {
+            "webp" => Some(Self::WebP),
+            "avif" => Some(Self::Avif),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::WebP => "webp",
+            Self::Avif => "avif",
+        }
+    }
+
+    fn mime_type(&self) -> &'static str {
+        match self {
+            Self::WebP => "image/webp",
+            Self::Avif => "image/avif",
+        }
+    }
+}
+
+/// The lowercase file ending of `path`
+fn extension(path: &str) -> Option<String> {
+    Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase())
+}
+
+/// Whether the image at `path` is compressed lossily, based on its file ending.
+/// WebP images are treated as lossy, since that's what they are mostly used for
+fn is_lossy(path: &str) -> bool {
+    matches!(extension(path).as_deref(), Some("jpg") | Some("jpeg") | Some("webp"))
+}
+
+/// The formats the image at `src` is converted to
+fn target_formats(src: &str, mut formats: Vec<ImageFormat>) -> Vec<ImageFormat> {
+    // WebP is only encoded losslessly, which is a lot larger than a lossy original
+    if is_lossy(src) {
+        formats.retain(|format| *format != ImageFormat::WebP);
+    }
+    // a variant in the format of the original would overwrite the original and its resized copies
+    let extension = extension(src);
+    formats.retain(|format| extension.as_deref() != Some(format.extension()));
+    formats
+}
+
+/// The image options set with `#+attr_html` for a single image. They replace the ones from the config
+#[derive(Default)]
+pub struct ImageOverrides {
+    widths: Option<Vec<u32>>,
+    formats: Option<Vec<ImageFormat>>,
+    sizes: Option<String>,
+}
+
+impl ImageOverrides {
+    /// Set the option `key`. Returns false if there is no image option with this name
+    pub fn insert(&mut self, key: &str, value: &str) -> Result<bool, String> {
+        match key {
+            "widths" => {
+                let widths = value
+                    .split_whitespace()
+                    .map(|width| width.parse::<u32>().ok().filter(|width| *width > 0))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| format!("Invalid image widths `{}`", value))?;
+                self.widths = Some(widths);
+            }
+            "formats" => {
+                let formats = value
+                    .split_whitespace()
+                    .map(ImageFormat::parse)
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| {
+                        format!("Invalid image formats `{}`. Expected webp or avif", value)
+                    })?;
+                self.formats = Some(formats);
+            }
+            "sizes" => self.sizes = Some(value.to_string()),
+            "responsive" => match value {
+                "t" => {}
+                "nil" => {
+                    self.widths = Some(Vec::new());
+                    self.formats = Some(Vec::new());
+                }
+                _ => return Err(format!("Invalid value `{}` for :responsive", value)),
+            },
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// An image with the variants that are generated for it and its intrinsic size
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ResponsiveImage {
+    /// The path of the image relative to the org file
+    pub src: String,
+    pub width: u32,
+    pub height: u32,
+    /// The widths of the resized variants, which are all smaller than the image
+    widths: Vec<u32>,
+    formats: Vec<ImageFormat>,
+    sizes: String,
+}
+
+/// A file generated for an image
+pub struct Variant {
+    /// The path relative to the org file
+    pub path: String,
+    /// The width it is resized to, or None for the full size
+    width: Option<u32>,
+    /// The format it is converted to, or None for the format of the original
+    format: Option<ImageFormat>,
+}
+
+impl ResponsiveImage {
+    /// Returns None if the size of the image can't be read, e.g. because it doesn't exist
+    pub fn new(
+        folder: &Path,
+        src: &str,
+        config: &ImageConfig,
+        overrides: &ImageOverrides,
+    ) -> Option<Self> {
+        let (width, height) = image::image_dimensions(folder.join(src)).ok()?;
+
+        let mut widths: Vec<u32> = overrides
+            .widths
+            .as_ref()
+            .unwrap_or(&config.widths)
+            .iter()
+            .copied()
+            .filter(|w| *w < width)
+            .collect();
+        widths.sort_unstable();
+        widths.dedup();
+
+        let formats = target_formats(
+            src,
+            overrides.formats.clone().unwrap_or_else(|| config.formats.clone()),
+        );
+
+        Some(ResponsiveImage {
+            src: src.to_string(),
+            width,
+            height,
+            widths,
+            formats,
+            sizes: overrides.sizes.clone().unwrap_or_else(|| config.sizes.clone()),
+        })
+    }
+
+    /// Whether the size of the image at `folder` is still the one it was rendered with
+    pub fn is_current(&self, folder: &Path) -> bool {
+        image::image_dimensions(folder.join(&self.src)).ok() == Some((self.width, self.height))
+    }
+
+    fn variant_path(&self, width: Option<u32>, format: Option<ImageFormat>) -> String {
+        let path = Path::new(&self.src);
+        let stem = path.file_stem().unwrap().to_str().unwrap();
+        let extension = match format {
+            Some(format) => format.extension(),
+            None => path.extension().unwrap().to_str().unwrap(),
+        };
+        let name = match width {
+            Some(width) => format!("{}-{}w.{}", stem, width, extension),
+            None => format!("{}.{}", stem, extension),
+        };
+        match path.parent() {
+            Some(parent) if parent != Path::new("") => format!("{}/{}", parent.display(), name),
+            _ => name,
+        }
+    }
+
+    /// All generated files. The original image is not part of them
+    pub fn variants(&self) -> Vec<Variant> {
+        let mut variants = Vec::new();
+        for format in std::iter::once(None).chain(self.formats.iter().copied().map(Some)) {
+            for width in self.widths.iter().copied().map(Some).chain(std::iter::once(None)) {
+                if width.is_none() && format.is_none() {
+                    continue;
+                }
+                variants.push(Variant {
+                    path: self.variant_path(width, format),
+                    width,
+                    format,
+                });
+            }
+        }
+        variants
+    }
+
+    fn srcset(&self, format: Option<ImageFormat>) -> String {
+        self.widths
+            .iter()
+            .map(|width| (self.variant_path(Some(*width), format), *width))
+            .chain(std::iter::once((self.variant_path(None, format), self.width)))
+            .map(|(path, width)| format!("./{} {}w", path, width))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Write the image as `<picture>` with a source for each format, or as plain `<img>`
+    /// if there are no variants
+    pub fn write_html<W: Write>(&self, w: &mut W, alt: Option<&str>, style: &str) -> Result<(), IOError> {
+        let responsive = !self.widths.is_empty() || !self.formats.is_empty();
+        if responsive {
+            write!(w, "<picture>")?;
+            for format in self.formats.iter() {
+                write!(
+                    w,
+                    "<source type=\"{}\" srcset=\"{}\" sizes=\"{}\">",
+                    format.mime_type(),
+                    HtmlEscape(&self.srcset(Some(*format))),
+                    HtmlEscape(&self.sizes)
+                )?;
+            }
+        }
+
+        write!(w, "<img src=\"./{}\"", HtmlEscape(&self.src))?;
+        if !self.widths.is_empty() {
+            write!(
+                w,
+                " srcset=\"{}\" sizes=\"{}\"",
+                HtmlEscape(&self.srcset(None)),
+                HtmlEscape(&self.sizes)
+            )?;
+        }
+        write!(w, " width=\"{}\" height=\"{}\"", self.width, self.height)?;
+        if let Some(alt) = alt {
+            write!(w, " alt=\"{}\"", HtmlEscape(alt))?;
+        }
+        write!(w, " loading=\"lazy\"{}>", style)?;
+
+        if responsive {
+            write!(w, "</picture>")?;
+        }
+        Ok(())
+    }
+}
+
+impl Variant {
+    /// Resize and convert `image` and write it to `dest`
+    pub fn write(&self, image: &DynamicImage, dest: &Path) -> Result<(), ImageError> {
+        let image = match self.width {
+            Some(width) => image.resize(width, u32::MAX, FilterType::Lanczos3),
+            None => image.clone(),
+        };
+
+        let format = match self.format {
+            None => return image.save(dest),
+            Some(format) => format,
+        };
+        // the encoders only support 8 bit images
+        let image = DynamicImage::ImageRgba8(image.to_rgba8());
+        let out = BufWriter::new(File::create(dest)?);
+        match format {
+            ImageFormat::WebP => image.write_with_encoder(WebPEncoder::new_lossless(out)),
+            ImageFormat::Avif => {
+                image.write_with_encoder(AvifEncoder::new_with_speed_quality(out, 8, 75))
+            }
+        }
+    }
+
+    /// The fingerprint of the variant for the build cache, based on the hash of the original
+    pub fn fingerprint(&self, source_hash: u64) -> u64 {
+        super::cache::hash((source_hash, self.width, self.format))
+    }
+}
+
+#[test]
+fn test_variants() {
+    let image = ResponsiveImage {
+        src: String::from("images/photo.png"),
+        width: 1200,
+        height: 800,
+        widths: vec![480, 960],
+        formats: vec![ImageFormat::WebP],
+        sizes: String::from("100vw"),
+    };
+    let paths: Vec<String> = image.variants().into_iter().map(|v| v.path).collect();
+    assert_eq!(
+        paths,
+        vec![
+            "images/photo-480w.png",
+            "images/photo-960w.png",
+            "images/photo-480w.webp",
+            "images/photo-960w.webp",
+            "images/photo.webp",
+        ]
+    );
+    assert_eq!(
+        image.srcset(Some(ImageFormat::WebP)),
+        "./images/photo-480w.webp 480w, ./images/photo-960w.webp 960w, ./images/photo.webp 1200w"
+    );
+    assert!(is_lossy("images/photo.JPG"));
+    assert!(!is_lossy("images/photo.png"));
+}
+
+#[test]
+fn test_webp_source() {
+    let formats = vec![ImageFormat::WebP, ImageFormat::Avif];
+    assert_eq!(target_formats("photo.webp", formats.clone()), vec![ImageFormat::Avif]);
+    assert_eq!(target_formats("photo.avif", formats.clone()), vec![ImageFormat::WebP]);
+    assert_eq!(target_formats("photo.png", formats.clone()), formats);
+
+    let image = ResponsiveImage {
+        src: String::from("photo.webp"),
+        width: 1200,
+        height: 800,
+        widths: vec![480],
+        formats: target_formats("photo.webp", vec![ImageFormat::WebP]),
+        sizes: String::from("100vw"),
+    };
+    let paths: Vec<String> = image.variants().into_iter().map(|v| v.path).collect();
+    assert_eq!(paths, vec!["photo-480w.webp"]);
+}
@@ -0,0 +1,1074 @@
+use std::fmt::Write;
+
+/// Errors for formulas that can't be converted. They are left for MathJax instead
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    UnknownCommand(String),
+    UnknownEnvironment(String),
+    /// A closing brace, `\right` or `\end` without its counterpart, or one that is missing
+    Unbalanced(String),
+    MissingArgument(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnknownCommand(cmd) => write!(f, "Unsupported command \\{}", cmd),
+            Self::UnknownEnvironment(env) => write!(f, "Unsupported environment {}", env),
+            Self::Unbalanced(token) => write!(f, "Unbalanced {}", token),
+            Self::MissingArgument(cmd) => write!(f, "Missing argument for \\{}", cmd),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A part of a text, which is either plain text or a formula
+#[derive(Debug, PartialEq)]
+pub enum Segment<'a> {
+    Text(&'a str),
+    Math {
+        /// The formula including its delimiters
+        source: &'a str,
+        /// The formula passed to the converter, or None if it isn't closed in the text
+        tex: Option<&'a str>,
+        display: bool,
+    },
+}
+
+/// The delimiters that MathJax recognizes by default
+const DELIMITERS: &[(&str, &str, bool)] = &[
+    ("\\(", "\\)", false),
+    ("\\[", "\\]", true),
+    ("$$", "$$", true),
+];
+
+/// Environments that are display formulas on their own, like in LaTeX
+const DISPLAY_ENVIRONMENTS: &[&str] = &[
+    "equation",
+    "equation*",
+    "align",
+    "align*",
+    "gather",
+    "gather*",
+    "multline",
+    "multline*",
+];
+
+/// The end of the formula `$...$` starting at `start`, following the rules of org: the opening
+/// dollar is neither preceded by another one nor followed by whitespace, `,`, `.` or `;`.
+/// The closing dollar is neither preceded by whitespace, `,` or `.` nor followed by a letter or digit
+fn single_dollar_end(text: &str, start: usize) -> Option<usize> {
+    if text[..start].ends_with('$') {
+        return None;
+    }
+    let inner = start + 1;
+    let first = text[inner..].chars().next()?;
+    if first.is_whitespace() || matches!(first, ',' | '.' | ';' | '$') {
+        return None;
+    }
+    let close = inner + text[inner..].find('$')?;
+    let last = text[..close].chars().next_back()?;
+    if last.is_whitespace() || matches!(last, ',' | '.') {
+        return None;
+    }
+    if text[close + 1..].starts_with(char::is_alphanumeric) {
+        return None;
+    }
+    Some(close + 1)
+}
+
+/// Split `text` at the formulas it contains
+pub fn split(text: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = text;
+    let mut searched = 0;
+    while let Some(offset) = rest[searched..].find(&['\\', '$'][..]) {
+        let start = searched + offset;
+        let candidate = &rest[start..];
+        if candidate.starts_with('$') && !candidate.starts_with("$$") {
+            // a dollar sign that doesn't start a formula, like in `$5`, is plain text
+            match single_dollar_end(rest, start) {
+                Some(end) => {
+                    if start > 0 {
+                        segments.push(Segment::Text(&rest[..start]));
+                    }
+                    segments.push(Segment::Math {
+                        source: &rest[start..end],
+                        tex: Some(&rest[start + 1..end - 1]),
+                        display: false,
+                    });
+                    rest = &rest[end..];
+                    searched = 0;
+                }
+                None => searched = start + 1,
+            }
+            continue;
+        }
+        let formula = DELIMITERS
+            .iter()
+            .find(|(open, _, _)| candidate.starts_with(open))
+            .map(|(open, close, display)| (open.len(), close.to_string(), *display))
+            .or_else(|| {
+                let name = candidate.strip_prefix("\\begin{")?.split('}').next()?;
+                if DISPLAY_ENVIRONMENTS.contains(&name) {
+                    // the environment is passed to the converter as well
+                    Some((0, format!("\\end{{{}}}", name), true))
+                } else {
+                    None
+                }
+            });
+        let (open, close, display) = match formula {
+            Some(formula) => formula,
+            None => {
+                // skip escaped characters like \$
+                searched = start + candidate.chars().take(2).map(char::len_utf8).sum::<usize>();
+                continue;
+            }
+        };
+
+        if start > 0 {
+            segments.push(Segment::Text(&rest[..start]));
+        }
+        let search_from = start + open.max(1);
+        match rest[search_from..].find(&close) {
+            Some(end) => {
+                let end = search_from + end;
+                let (tex_end, source_end) = if open == 0 {
+                    (end + close.len(), end + close.len())
+                } else {
+                    (end, end + close.len())
+                };
+                segments.push(Segment::Math {
+                    source: &rest[start..source_end],
+                    tex: Some(&rest[start + open..tex_end]),
+                    display,
+                });
+                rest = &rest[source_end..];
+            }
+            None => {
+                segments.push(Segment::Math {
+                    source: &rest[start..],
+                    tex: None,
+                    display,
+                });
+                rest = "";
+            }
+        }
+        searched = 0;
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+    segments
+}
+
+/// Convert a formula to MathML. The tex source is kept as annotation
+pub fn to_mathml(tex: &str, display: bool) -> Result<String, Error> {
+    let mut parser = Parser {
+        source: tex,
+        pos: 0,
+        display,
+        variant: None,
+    };
+    let row = parser.parse_row()?;
+    if let Some(token) = parser.next_token() {
+        return Err(Error::Unbalanced(token.to_string()));
+    }
+    Ok(format!(
+        "<math{}><semantics>{}<annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+        if display { " display=\"block\"" } else { "" },
+        mrow(row),
+        escape(tex.trim())
+    ))
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Token<'a> {
+    /// The name of a command without the backslash
+    Command(&'a str),
+    Char(char),
+    Open,
+    Close,
+    Sup,
+    Sub,
+    Align,
+}
+
+impl std::fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Command(name) => write!(f, "\\{}", name),
+            Self::Char(c) => write!(f, "{}", c),
+            Self::Open => write!(f, "{{"),
+            Self::Close => write!(f, "}}"),
+            Self::Sup => write!(f, "^"),
+            Self::Sub => write!(f, "_"),
+            Self::Align => write!(f, "&"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Class {
+    /// A variable or constant, which is italic if it is a single letter
+    Ident,
+    /// An upright identifier, like capital greek letters
+    Upright,
+    Operator,
+    /// A large operator with limits below and above it in display formulas
+    Limits,
+}
+
+const SYMBOLS: &[(&str, Class, &str)] = &[
+    ("alpha", Class::Ident, "α"),
+    ("beta", Class::Ident, "β"),
+    ("gamma", Class::Ident, "γ"),
+    ("delta", Class::Ident, "δ"),
+    ("epsilon", Class::Ident, "ϵ"),
+    ("varepsilon", Class::Ident, "ε"),
+    ("zeta", Class::Ident, "ζ"),
+    ("eta", Class::Ident, "η"),
+    ("theta", Class::Ident, "θ"),
+    ("vartheta", Class::Ident, "ϑ"),
+    ("iota", Class::Ident, "ι"),
+    ("kappa", Class::Ident, "κ"),
+    ("lambda", Class::Ident, "λ"),
+    ("mu", Class::Ident, "μ"),
+    ("nu", Class::Ident, "ν"),
+    ("xi", Class::Ident, "ξ"),
+    ("pi", Class::Ident, "π"),
+    ("varpi", Class::Ident, "ϖ"),
+    ("rho", Class::Ident, "ρ"),
+    ("varrho", Class::Ident, "ϱ"),
+    ("sigma", Class::Ident, "σ"),
+    ("varsigma", Class::Ident, "ς"),
+    ("tau", Class::Ident, "τ"),
+    ("upsilon", Class::Ident, "υ"),
+    ("phi", Class::Ident, "ϕ"),
+    ("varphi", Class::Ident, "φ"),
+    ("chi", Class::Ident, "χ"),
+    ("psi", Class::Ident, "ψ"),
+    ("omega", Class::Ident, "ω"),
+    ("Gamma", Class::Upright, "Γ"),
+    ("Delta", Class::Upright, "Δ"),
+    ("Theta", Class::Upright, "Θ"),
+    ("Lambda", Class::Upright, "Λ"),
+    ("Xi", Class::Upright, "Ξ"),
+    ("Pi", Class::Upright, "Π"),
+    ("Sigma", Class::Upright, "Σ"),
+    ("Upsilon", Class::Upright, "Υ"),
+    ("Phi", Class::Upright, "Φ"),
+    ("Psi", Class::Upright, "Ψ"),
+    ("Omega", Class::Upright, "Ω"),
+    ("infty", Class::Upright, "∞"),
+    ("partial", Class::Ident, "∂"),
+    ("nabla", Class::Upright, "∇"),
+    ("emptyset", Class::Upright, "∅"),
+    ("varnothing", Class::Upright, "∅"),
+    ("hbar", Class::Ident, "ℏ"),
+    ("ell", Class::Ident, "ℓ"),
+    ("Re", Class::Upright, "ℜ"),
+    ("Im", Class::Upright, "ℑ"),
+    ("aleph", Class::Upright, "ℵ"),
+    ("angle", Class::Upright, "∠"),
+    ("triangle", Class::Upright, "△"),
+    ("prime", Class::Operator, "′"),
+    ("pm", Class::Operator, "±"),
+    ("mp", Class::Operator, "∓"),
+    ("times", Class::Operator, "×"),
+    ("div", Class::Operator, "÷"),
+    ("cdot", Class::Operator, "⋅"),
+    ("ast", Class::Operator, "∗"),
+    ("star", Class::Operator, "⋆"),
+    ("circ", Class::Operator, "∘"),
+    ("bullet", Class::Operator, "∙"),
+    ("oplus", Class::Operator, "⊕"),
+    ("otimes", Class::Operator, "⊗"),
+    ("cap", Class::Operator, "∩"),
+    ("cup", Class::Operator, "∪"),
+    ("setminus", Class::Operator, "∖"),
+    ("wedge", Class::Operator, "∧"),
+    ("land", Class::Operator, "∧"),
+    ("vee", Class::Operator, "∨"),
+    ("lor", Class::Operator, "∨"),
+    ("neg", Class::Operator, "¬"),
+    ("lnot", Class::Operator, "¬"),
+    ("leq", Class::Operator, "≤"),
+    ("le", Class::Operator, "≤"),
+    ("geq", Class::Operator, "≥"),
+    ("ge", Class::Operator, "≥"),
+    ("neq", Class::Operator, "≠"),
+    ("ne", Class::Operator, "≠"),
+    ("ll", Class::Operator, "≪"),
+    ("gg", Class::Operator, "≫"),
+    ("approx", Class::Operator, "≈"),
+    ("sim", Class::Operator, "∼"),
+    ("simeq", Class::Operator, "≃"),
+    ("cong", Class::Operator, "≅"),
+    ("equiv", Class::Operator, "≡"),
+    ("propto", Class::Operator, "∝"),
+    ("subset", Class::Operator, "⊂"),
+    ("supset", Class::Operator, "⊃"),
+    ("subseteq", Class::Operator, "⊆"),
+    ("supseteq", Class::Operator, "⊇"),
+    ("in", Class::Operator, "∈"),
+    ("notin", Class::Operator, "∉"),
+    ("ni", Class::Operator, "∋"),
+    ("mid", Class::Operator, "∣"),
+    ("parallel", Class::Operator, "∥"),
+    ("perp", Class::Operator, "⊥"),
+    ("to", Class::Operator, "→"),
+    ("rightarrow", Class::Operator, "→"),
+    ("leftarrow", Class::Operator, "←"),
+    ("gets", Class::Operator, "←"),
+    ("leftrightarrow", Class::Operator, "↔"),
+    ("Rightarrow", Class::Operator, "⇒"),
+    ("Leftarrow", Class::Operator, "⇐"),
+    ("Leftrightarrow", Class::Operator, "⇔"),
+    ("implies", Class::Operator, "⟹"),
+    ("iff", Class::Operator, "⟺"),
+    ("mapsto", Class::Operator, "↦"),
+    ("uparrow", Class::Operator, "↑"),
+    ("downarrow", Class::Operator, "↓"),
+    ("forall", Class::Operator, "∀"),
+    ("exists", Class::Operator, "∃"),
+    ("nexists", Class::Operator, "∄"),
+    ("ldots", Class::Operator, "…"),
+    ("dots", Class::Operator, "…"),
+    ("cdots", Class::Operator, "⋯"),
+    ("vdots", Class::Operator, "⋮"),
+    ("ddots", Class::Operator, "⋱"),
+    ("colon", Class::Operator, ":"),
+    ("langle", Class::Operator, "⟨"),
+    ("rangle", Class::Operator, "⟩"),
+    ("lceil", Class::Operator, "⌈"),
+    ("rceil", Class::Operator, "⌉"),
+    ("lfloor", Class::Operator, "⌊"),
+    ("rfloor", Class::Operator, "⌋"),
+    ("vert", Class::Operator, "|"),
+    ("lvert", Class::Operator, "|"),
+    ("rvert", Class::Operator, "|"),
+    ("Vert", Class::Operator, "‖"),
+    ("|", Class::Operator, "‖"),
+    ("{", Class::Operator, "{"),
+    ("}", Class::Operator, "}"),
+    ("_", Class::Operator, "_"),
+    ("%", Class::Operator, "%"),
+    ("$", Class::Operator, "$"),
+    ("#", Class::Operator, "#"),
+    ("&", Class::Operator, "&"),
+    ("backslash", Class::Operator, "\\"),
+    ("sum", Class::Limits, "∑"),
+    ("prod", Class::Limits, "∏"),
+    ("coprod", Class::Limits, "∐"),
+    ("bigcup", Class::Limits, "⋃"),
+    ("bigcap", Class::Limits, "⋂"),
+    ("bigoplus", Class::Limits, "⨁"),
+    ("bigotimes", Class::Limits, "⨂"),
+    ("bigvee", Class::Limits, "⋁"),
+    ("bigwedge", Class::Limits, "⋀"),
+    ("int", Class::Operator, "∫"),
+    ("iint", Class::Operator, "∬"),
+    ("iiint", Class::Operator, "∭"),
+    ("oint", Class::Operator, "∮"),
+];
+
+/// Functions that are written as upright text
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "log", "ln", "lg", "exp", "det", "dim", "ker", "deg", "gcd", "arg", "hom", "Pr",
+];
+
+/// Functions with limits below them in display formulas, e.g. `\lim_{x \to 0}`
+const LIMIT_FUNCTIONS: &[&str] = &[
+    "lim", "max", "min", "sup", "inf", "limsup", "liminf", "argmax", "argmin",
+];
+
+const SPACES: &[(&str, &str)] = &[
+    (",", "0.1667em"),
+    (":", "0.2222em"),
+    (">", "0.2222em"),
+    (";", "0.2778em"),
+    ("!", "-0.1667em"),
+    (" ", "0.25em"),
+    ("quad", "1em"),
+    ("qquad", "2em"),
+];
+
+/// Accents above (or below) their argument, and whether they stretch over all of it
+const ACCENTS: &[(&str, &str, bool)] = &[
+    ("hat", "^", false),
+    ("widehat", "^", true),
+    ("bar", "¯", false),
+    ("overline", "‾", true),
+    ("vec", "→", false),
+    ("overrightarrow", "→", true),
+    ("tilde", "~", false),
+    ("widetilde", "~", true),
+    ("dot", "˙", false),
+    ("ddot", "¨", false),
+];
+
+/// The font variants of `\mathbf` and similar commands
+#[derive(Copy, Clone, PartialEq)]
+enum Variant {
+    Normal,
+    Bold,
+    BoldItalic,
+    DoubleStruck,
+    Script,
+    Fraktur,
+    SansSerif,
+    Monospace,
+}
+
+const VARIANTS: &[(&str, Variant)] = &[
+    ("mathrm", Variant::Normal),
+    ("mathbf", Variant::Bold),
+    ("boldsymbol", Variant::BoldItalic),
+    ("mathbb", Variant::DoubleStruck),
+    ("mathcal", Variant::Script),
+    ("mathscr", Variant::Script),
+    ("mathfrak", Variant::Fraktur),
+    ("mathsf", Variant::SansSerif),
+    ("mathtt", Variant::Monospace),
+];
+
+impl Variant {
+    /// The code points of the capital and small letters and the digits in the
+    /// Mathematical Alphanumeric Symbols block
+    fn offsets(&self) -> (u32, u32, Option<u32>) {
+        match self {
+            Self::Normal => unreachable!(),
+            Self::Bold => (0x1D400, 0x1D41A, Some(0x1D7CE)),
+            Self::BoldItalic => (0x1D468, 0x1D482, Some(0x1D7CE)),
+            Self::DoubleStruck => (0x1D538, 0x1D552, Some(0x1D7D8)),
+            Self::Script => (0x1D49C, 0x1D4B6, None),
+            Self::Fraktur => (0x1D504, 0x1D51E, None),
+            Self::SansSerif => (0x1D5A0, 0x1D5BA, Some(0x1D7E2)),
+            Self::Monospace => (0x1D670, 0x1D68A, Some(0x1D7F6)),
+        }
+    }
+
+    /// Letters that were already part of unicode before the block was added
+    fn exception(&self, c: char) -> Option<char> {
+        let exceptions: &[(char, char)] = match self {
+            Self::DoubleStruck => &[
+                ('C', 'ℂ'),
+                ('H', 'ℍ'),
+                ('N', 'ℕ'),
+                ('P', 'ℙ'),
+                ('Q', 'ℚ'),
+                ('R', 'ℝ'),
+                ('Z', 'ℤ'),
+            ],
+            Self::Script => &[
+                ('B', 'ℬ'),
+                ('E', 'ℰ'),
+                ('F', 'ℱ'),
+                ('H', 'ℋ'),
+                ('I', 'ℐ'),
+                ('L', 'ℒ'),
+                ('M', 'ℳ'),
+                ('R', 'ℛ'),
+                ('e', 'ℯ'),
+                ('g', 'ℊ'),
+                ('o', 'ℴ'),
+            ],
+            Self::Fraktur => &[('C', 'ℭ'), ('H', 'ℌ'), ('I', 'ℑ'), ('R', 'ℜ'), ('Z', 'ℨ')],
+            _ => &[],
+        };
+        exceptions
+            .iter()
+            .find(|(from, _)| *from == c)
+            .map(|(_, to)| *to)
+    }
+
+    fn apply(&self, c: char) -> char {
+        if let Some(c) = self.exception(c) {
+            return c;
+        }
+        let (capital, small, digit) = self.offsets();
+        let code = match c {
+            'A'..='Z' => capital + (c as u32 - 'A' as u32),
+            'a'..='z' => small + (c as u32 - 'a' as u32),
+            '0'..='9' => match digit {
+                Some(digit) => digit + (c as u32 - '0' as u32),
+                None => return c,
+            },
+            _ => return c,
+        };
+        std::char::from_u32(code).unwrap_or(c)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Combine several elements into one
+fn mrow(items: Vec<String>) -> String {
+    if items.len() == 1 {
+        items.into_iter().next().unwrap()
+    } else {
+        format!("<mrow>{}</mrow>", items.concat())
+    }
+}
+
+fn operator(text: &str) -> String {
+    format!("<mo>{}</mo>", escape(text))
+}
+
+fn fence(text: &str) -> String {
+    format!("<mo fence=\"true\" stretchy=\"true\">{}</mo>", escape(text))
+}
+
+/// An element together with the information needed to attach sub- and superscripts
+struct Atom {
+    mathml: String,
+    limits: bool,
+}
+
+impl From<String> for Atom {
+    fn from(mathml: String) -> Self {
+        Atom {
+            mathml,
+            limits: false,
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    pos: usize,
+    /// Whether the formula is displayed as block, which places limits below operators
+    display: bool,
+    /// The font variant for letters, set by `\mathbf` and similar commands
+    variant: Option<Variant>,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.source[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn next_token(&mut self) -> Option<Token<'a>> {
+        self.skip_whitespace();
+        let mut chars = self.source[self.pos..].chars();
+        let c = chars.next()?;
+        self.pos += c.len_utf8();
+        Some(match c {
+            '{' => Token::Open,
+            '}' => Token::Close,
+            '^' => Token::Sup,
+            '_' => Token::Sub,
+            '&' => Token::Align,
+            '\\' => {
+                let rest = &self.source[self.pos..];
+                let len = match rest.find(|c: char| !c.is_ascii_alphabetic()) {
+                    Some(0) => rest.chars().next().map_or(0, char::len_utf8),
+                    Some(len) => len,
+                    None => rest.len(),
+                };
+                self.pos += len;
+                Token::Command(&rest[..len])
+            }
+            c => Token::Char(c),
+        })
+    }
+
+    fn peek_token(&mut self) -> Option<Token<'a>> {
+        let pos = self.pos;
+        let token = self.next_token();
+        self.pos = pos;
+        token
+    }
+
+    /// Read the content of a group as text, e.g. the argument of `\text`
+    fn read_raw_group(&mut self, command: &str) -> Result<&'a str, Error> {
+        if self.next_token() != Some(Token::Open) {
+            return Err(Error::MissingArgument(command.to_string()));
+        }
+        let start = self.pos;
+        let mut depth = 0;
+        for (index, c) in self.source[start..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => {
+                    self.pos = start + index + 1;
+                    return Ok(&self.source[start..start + index]);
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+        Err(Error::Unbalanced(String::from("{")))
+    }
+
+    /// Parse elements until the end of the current group, cell or row
+    fn parse_row(&mut self) -> Result<Vec<String>, Error> {
+        let mut row = Vec::new();
+        while let Some(token) = self.peek_token() {
+            match token {
+                Token::Close
+                | Token::Align
+                | Token::Command("\\")
+                | Token::Command("right")
+                | Token::Command("end") => break,
+                Token::Command("displaystyle") | Token::Command("textstyle") => {
+                    self.next_token();
+                    let rest = mrow(self.parse_row()?);
+                    row.push(format!(
+                        "<mstyle displaystyle=\"{}\">{}</mstyle>",
+                        token == Token::Command("displaystyle"),
+                        rest
+                    ));
+                }
+                _ => row.push(self.parse_scripted()?),
+            }
+        }
+        Ok(row)
+    }
+
+    /// Parse a group in braces or a single token, e.g. the argument of `\frac` or `^`
+    fn parse_argument(&mut self, command: &str) -> Result<String, Error> {
+        match self.peek_token() {
+            Some(Token::Open) => {
+                self.next_token();
+                let row = self.parse_row()?;
+                match self.next_token() {
+                    Some(Token::Close) => Ok(mrow(row)),
+                    _ => Err(Error::Unbalanced(String::from("{"))),
+                }
+            }
+            Some(Token::Close) | Some(Token::Align) | None => {
+                Err(Error::MissingArgument(command.to_string()))
+            }
+            Some(_) => Ok(self.parse_atom()?.mathml),
+        }
+    }
+
+    /// Parse an element with its sub- and superscripts
+    fn parse_scripted(&mut self) -> Result<String, Error> {
+        let base = self.parse_atom()?;
+        let mut limits = base.limits && self.display;
+        let mut sub = None;
+        let mut sup = None;
+        let mut primes = String::new();
+        while let Some(token) = self.peek_token() {
+            match token {
+                Token::Sub if sub.is_none() => {
+                    self.next_token();
+                    sub = Some(self.parse_argument("_")?);
+                }
+                Token::Sup if sup.is_none() => {
+                    self.next_token();
+                    sup = Some(self.parse_argument("^")?);
+                }
+                Token::Char('\'') if sup.is_none() => {
+                    self.next_token();
+                    primes.push('′');
+                }
+                Token::Command("limits") => {
+                    self.next_token();
+                    limits = true;
+                }
+                Token::Command("nolimits") => {
+                    self.next_token();
+                    limits = false;
+                }
+                _ => break,
+            }
+        }
+
+        if !primes.is_empty() {
+            let primes = operator(&primes);
+            sup = Some(match sup {
+                Some(sup) => format!("<mrow>{}{}</mrow>", primes, sup),
+                None => primes,
+            });
+        }
+        let (under, over, both) = if limits {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+        Ok(match (sub, sup) {
+            (None, None) => base.mathml,
+            (Some(sub), None) => format!("<{0}>{1}{2}</{0}>", under, base.mathml, sub),
+            (None, Some(sup)) => format!("<{0}>{1}{2}</{0}>", over, base.mathml, sup),
+            (Some(sub), Some(sup)) => format!("<{0}>{1}{2}{3}</{0}>", both, base.mathml, sub, sup),
+        })
+    }
+
+    fn parse_atom(&mut self) -> Result<Atom, Error> {
+        let token = match self.next_token() {
+            Some(token) => token,
+            None => return Err(Error::MissingArgument(String::from("^"))),
+        };
+        match token {
+            Token::Open => {
+                let row = self.parse_row()?;
+                match self.next_token() {
+                    Some(Token::Close) => Ok(mrow(row).into()),
+                    _ => Err(Error::Unbalanced(String::from("{"))),
+                }
+            }
+            // scripts without base, e.g. {}^{14}C
+            Token::Sup | Token::Sub => {
+                self.pos -= 1;
+                Ok(String::from("<mrow></mrow>").into())
+            }
+            Token::Close | Token::Align => Err(Error::Unbalanced(token.to_string())),
+            Token::Char(c) => Ok(self.parse_char(c).into()),
+            Token::Command(name) => self.parse_command(name),
+        }
+    }
+
+    fn parse_char(&mut self, c: char) -> String {
+        if c.is_ascii_digit() {
+            let mut number = c.to_string();
+            let rest = &self.source[self.pos..];
+            let len = rest
+                .char_indices()
+                .find(|(index, c)| {
+                    !(c.is_ascii_digit()
+                        || (*c == '.'
+                            && rest[index + 1..].starts_with(|c: char| c.is_ascii_digit())))
+                })
+                .map_or(rest.len(), |(index, _)| index);
+            number.push_str(&rest[..len]);
+            self.pos += len;
+            return match self.variant {
+                Some(variant) if variant != Variant::Normal => {
+                    format!(
+                        "<mn>{}</mn>",
+                        number.chars().map(|c| variant.apply(c)).collect::<String>()
+                    )
+                }
+                _ => format!("<mn>{}</mn>", number),
+            };
+        }
+        if c.is_alphabetic() {
+            return match self.variant {
+                None => format!("<mi>{}</mi>", c),
+                Some(Variant::Normal) => format!("<mi mathvariant=\"normal\">{}</mi>", c),
+                Some(variant) => format!("<mi>{}</mi>", variant.apply(c)),
+            };
+        }
+        match c {
+            '-' => operator("−"),
+            '*' => operator("∗"),
+            '\'' => operator("′"),
+            '~' => String::from("<mspace width=\"0.25em\"></mspace>"),
+            c => operator(&c.to_string()),
+        }
+    }
+
+    fn parse_command(&mut self, name: &'a str) -> Result<Atom, Error> {
+        if let Some((_, class, text)) = SYMBOLS.iter().find(|(symbol, _, _)| *symbol == name) {
+            return Ok(match class {
+                Class::Ident => format!("<mi>{}</mi>", text).into(),
+                Class::Upright => format!("<mi mathvariant=\"normal\">{}</mi>", text).into(),
+                Class::Operator => operator(text).into(),
+                Class::Limits => Atom {
+                    mathml: operator(text),
+                    limits: true,
+                },
+            });
+        }
+        if FUNCTIONS.contains(&name) {
+            return Ok(format!("<mi>{}</mi>", name).into());
+        }
+        if LIMIT_FUNCTIONS.contains(&name) {
+            let name = match name {
+                "limsup" => "lim sup",
+                "liminf" => "lim inf",
+                "argmax" => "arg max",
+                "argmin" => "arg min",
+                name => name,
+            };
+            return Ok(Atom {
+                mathml: format!("<mo movablelimits=\"true\">{}</mo>", name),
+                limits: true,
+            });
+        }
+        if let Some((_, width)) = SPACES.iter().find(|(space, _)| *space == name) {
+            return Ok(format!("<mspace width=\"{}\"></mspace>", width).into());
+        }
+        if let Some((_, accent, stretchy)) = ACCENTS.iter().find(|(accent, _, _)| *accent == name) {
+            let base = self.parse_argument(name)?;
+            return Ok(format!(
+                "<mover accent=\"true\">{}<mo stretchy=\"{}\">{}</mo></mover>",
+                base, stretchy, accent
+            )
+            .into());
+        }
+        if let Some((_, variant)) = VARIANTS.iter().find(|(command, _)| *command == name) {
+            let outer = self.variant.replace(*variant);
+            let argument = self.parse_argument(name);
+            self.variant = outer;
+            return Ok(argument?.into());
+        }
+
+        Ok(match name {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.parse_argument(name)?;
+                let denominator = self.parse_argument(name)?;
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "binom" => {
+                let n = self.parse_argument(name)?;
+                let k = self.parse_argument(name)?;
+                format!(
+                    "<mrow>{}<mfrac linethickness=\"0\">{}{}</mfrac>{}</mrow>",
+                    fence("("),
+                    n,
+                    k,
+                    fence(")")
+                )
+            }
+            "sqrt" => {
+                if self.peek_token() == Some(Token::Char('[')) {
+                    self.next_token();
+                    let mut index = Vec::new();
+                    while self.peek_token() != Some(Token::Char(']')) {
+                        if self.peek_token().is_none() {
+                            return Err(Error::Unbalanced(String::from("[")));
+                        }
+                        index.push(self.parse_scripted()?);
+                    }
+                    self.next_token();
+                    let radicand = self.parse_argument(name)?;
+                    format!("<mroot>{}{}</mroot>", radicand, mrow(index))
+                } else {
+                    format!("<msqrt>{}</msqrt>", self.parse_argument(name)?)
+                }
+            }
+            "text" | "textrm" | "mbox" => {
+                let text = self.read_raw_group(name)?;
+                // leading and trailing spaces would be removed otherwise
+                let text = escape(text).replace(' ', "\u{a0}");
+                format!("<mtext>{}</mtext>", text)
+            }
+            "operatorname" => {
+                let text = self.read_raw_group(name)?;
+                format!("<mi>{}</mi>", escape(text.trim()))
+            }
+            "overset" | "underset" | "stackrel" => {
+                let script = self.parse_argument(name)?;
+                let base = self.parse_argument(name)?;
+                let tag = if name == "underset" {
+                    "munder"
+                } else {
+                    "mover"
+                };
+                format!("<{0}>{1}{2}</{0}>", tag, base, script)
+            }
+            "underline" => {
+                let base = self.parse_argument(name)?;
+                format!(
+                    "<munder accentunder=\"true\">{}<mo stretchy=\"true\">_</mo></munder>",
+                    base
+                )
+            }
+            "left" => {
+                let open = self.read_delimiter(name)?;
+                let row = self.parse_row()?;
+                if self.next_token() != Some(Token::Command("right")) {
+                    return Err(Error::Unbalanced(String::from("\\left")));
+                }
+                let close = self.read_delimiter("right")?;
+                format!(
+                    "<mrow>{}{}{}</mrow>",
+                    fence(&open),
+                    row.concat(),
+                    fence(&close)
+                )
+            }
+            "begin" => self.parse_environment()?,
+            name => return Err(Error::UnknownCommand(name.to_string())),
+        }
+        .into())
+    }
+
+    /// The delimiter after `\left` or `\right`. `.` is an invisible delimiter
+    fn read_delimiter(&mut self, command: &str) -> Result<String, Error> {
+        match self.next_token() {
+            Some(Token::Char('.')) => Ok(String::new()),
+            Some(Token::Char(c)) => Ok(c.to_string()),
+            Some(Token::Command(name)) => {
+                match SYMBOLS.iter().find(|(symbol, _, _)| *symbol == name) {
+                    Some((_, Class::Operator, text)) => Ok(text.to_string()),
+                    _ => Err(Error::UnknownCommand(name.to_string())),
+                }
+            }
+            _ => Err(Error::MissingArgument(command.to_string())),
+        }
+    }
+
+    fn parse_environment(&mut self) -> Result<String, Error> {
+        let name = self.read_raw_group("begin")?.trim();
+        let (open, close, align) = match name {
+            "equation" | "equation*" => {
+                let row = self.parse_row()?;
+                self.expect_end(name)?;
+                return Ok(mrow(row));
+            }
+            "matrix" | "smallmatrix" => ("", "", None),
+            "pmatrix" => ("(", ")", None),
+            "bmatrix" => ("[", "]", None),
+            "Bmatrix" => ("{", "}", None),
+            "vmatrix" => ("|", "|", None),
+            "Vmatrix" => ("‖", "‖", None),
+            "cases" => ("{", "", Some("left left")),
+            "align" | "align*" | "aligned" | "split" => ("", "", Some("right left")),
+            "gather" | "gather*" | "gathered" | "multline" | "multline*" => ("", "", None),
+            name => return Err(Error::UnknownEnvironment(name.to_string())),
+        };
+
+        let mut rows = Vec::new();
+        loop {
+            let mut cells = Vec::new();
+            loop {
+                cells.push(mrow(self.parse_row()?));
+                if self.peek_token() != Some(Token::Align) {
+                    break;
+                }
+                self.next_token();
+            }
+            rows.push(cells);
+            if self.peek_token() != Some(Token::Command("\\")) {
+                break;
+            }
+            self.next_token();
+        }
+        self.expect_end(name)?;
+        // a trailing \\ doesn't start another row
+        if rows.len() > 1 && rows[rows.len() - 1] == [String::from("<mrow></mrow>")] {
+            rows.pop();
+        }
+
+        let mut table = String::from("<mtable");
+        if let Some(align) = align {
+            write!(table, " columnalign=\"{}\"", align).unwrap();
+        }
+        table.push('>');
+        for row in rows {
+            table.push_str("<mtr>");
+            for cell in row {
+                write!(table, "<mtd>{}</mtd>", cell).unwrap();
+            }
+            table.push_str("</mtr>");
+        }
+        table.push_str("</mtable>");
+
+        if open.is_empty() && close.is_empty() {
+            return Ok(table);
+        }
+        let open = if open.is_empty() {
+            String::new()
+        } else {
+            fence(open)
+        };
+        let close = if close.is_empty() {
+            String::new()
+        } else {
+            fence(close)
+        };
+        Ok(format!("<mrow>{}{}{}</mrow>", open, table, close))
+    }
+
+    fn expect_end(&mut self, name: &str) -> Result<(), Error> {
+        if self.next_token() != Some(Token::Command("end"))
+            || self.read_raw_group("end")?.trim() != name
+        {
+            return Err(Error::Unbalanced(format!("\\begin{{{}}}", name)));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_split() {
+    let segments = split("Let \\(x^2\\) be $5 and\n\\begin{equation}\na = b\n\\end{equation} \\[y");
+    assert_eq!(
+        segments,
+        vec![
+            Segment::Text("Let "),
+            Segment::Math {
+                source: "\\(x^2\\)",
+                tex: Some("x^2"),
+                display: false
+            },
+            Segment::Text(" be $5 and\n"),
+            Segment::Math {
+                source: "\\begin{equation}\na = b\n\\end{equation}",
+                tex: Some("\\begin{equation}\na = b\n\\end{equation}"),
+                display: true
+            },
+            Segment::Text(" "),
+            Segment::Math {
+                source: "\\[y",
+                tex: None,
+                display: true
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_split_single_dollar() {
+    let formulas = |text| {
+        split(text)
+            .into_iter()
+            .filter_map(|segment| match segment {
+                Segment::Math { tex, .. } => tex,
+                Segment::Text(_) => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(formulas("Let $x$ and $a + b$, so $y^2$."), vec!["x", "a + b", "y^2"]);
+    assert_eq!(formulas("It costs $5 and $10 now"), Vec::<&str>::new());
+    assert_eq!(formulas("$ x$ and $x $ and $x$y and \\$x$"), Vec::<&str>::new());
+    assert_eq!(formulas("$$x$$"), vec!["x"]);
+}
+
+#[test]
+fn test_to_mathml() {
+    let mathml = |tex| {
+        let math = to_mathml(tex, false).unwrap();
+        let start = "<math><semantics>".len();
+        math[start..math.find("<annotation").unwrap()].to_string()
+    };
+    assert_eq!(
+        mathml("x^2 + 1.5"),
+        "<mrow><msup><mi>x</mi><mn>2</mn></msup><mo>+</mo><mn>1.5</mn></mrow>"
+    );
+    assert_eq!(
+        mathml("\\frac{a}{\\sqrt{b}}"),
+        "<mfrac><mi>a</mi><msqrt><mi>b</mi></msqrt></mfrac>"
+    );
+    assert_eq!(mathml("\\mathbb{R}"), "<mi>ℝ</mi>");
+    assert_eq!(
+        mathml("\\left( \\alpha \\right)"),
+        "<mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mi>α</mi><mo fence=\"true\" stretchy=\"true\">)</mo></mrow>"
+    );
+    assert_eq!(
+        to_mathml("\\sum_{i=1}^n i", true).unwrap(),
+        "<math display=\"block\"><semantics><mrow><munderover><mo>∑</mo><mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></munderover><mi>i</mi></mrow>\
+         <annotation encoding=\"application/x-tex\">\\sum_{i=1}^n i</annotation></semantics></math>"
+    );
+    assert_eq!(
+        to_mathml("\\unknown x", false),
+        Err(Error::UnknownCommand(String::from("unknown")))
+    );
+    assert_eq!(
+        to_mathml("{x", false),
+        Err(Error::Unbalanced(String::from("{")))
+    );
+}
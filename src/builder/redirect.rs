@@ -0,0 +1,88 @@
+use std::fs;
+use std::fs::File;
+use std::io::{Error as IOError, Write};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::website::{BlogElement, Website};
+use super::Mode;
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RedirectFormat {
+    /// The `_redirects` file used by netlify and cloudflare pages
+    Netlify,
+    /// A map for the nginx `map` directive, written to `redirects.map`
+    Nginx,
+}
+
+impl RedirectFormat {
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Self::Netlify => "_redirects",
+            Self::Nginx => "redirects.map",
+        }
+    }
+}
+
+/// Escape text for html attributes
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// A page that immediately redirects to `url`, for servers without support for redirects
+fn redirect_page(url: &str) -> String {
+    let url = escape(url);
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Redirecting…</title>\n\
+         <link rel=\"canonical\" href=\"{0}\">\n<meta http-equiv=\"refresh\" content=\"0; url={0}\">\n\
+         </head>\n<body>\n<p>This page has moved to <a href=\"{0}\">{0}</a>.</p>\n</body>\n</html>\n",
+        url
+    )
+}
+
+/// Write a redirect page for each alias into the folder at `path`, as well as the redirect
+/// maps enabled in the config. Returns the paths of the written files relative to `path`
+pub fn write<TMode: Mode>(
+    website: &Website,
+    mode: &TMode,
+    path: &Path,
+) -> Result<Vec<String>, IOError> {
+    let mut written = Vec::new();
+    let mut redirects = Vec::new();
+    for (alias, file) in website.aliases.iter() {
+        let target = website.resolve_path(file).unwrap();
+        let relative_path = alias.trim_start_matches('/').to_string() + "/index.html";
+        let dest = path.join(&relative_path);
+        fs::create_dir_all(dest.parent().unwrap())?;
+        fs::write(&dest, redirect_page(&target.url(website, mode.base_url())))?;
+        written.push(relative_path);
+
+        // the maps are independent of the mode, so the target is relative to the base url
+        let target = target.url(website, String::new());
+        redirects.push((alias, if target.is_empty() { "/".to_string() } else { target }));
+    }
+
+    for format in website.config.redirects.formats.iter() {
+        let mut out = File::create(path.join(format.file_name()))?;
+        for (alias, target) in redirects.iter() {
+            match format {
+                RedirectFormat::Netlify => writeln!(out, "{} {} 301", alias, target)?,
+                RedirectFormat::Nginx => writeln!(out, "{} {};", alias, target)?,
+            }
+        }
+        written.push(format.file_name().to_string());
+    }
+    Ok(written)
+}
+
+#[test]
+fn test_redirect_page() {
+    let page = redirect_page("https://example.com/blog/new?a&b");
+    assert!(page.contains("<link rel=\"canonical\" href=\"https://example.com/blog/new?a&amp;b\">"));
+    assert!(page.contains("content=\"0; url=https://example.com/blog/new?a&amp;b\""));
+}
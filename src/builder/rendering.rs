@@ -1,33 +1,106 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{Error as IOError, Write};
+use std::path::{Component, Path, PathBuf};
 use std::string::FromUtf8Error;
+use std::sync::Arc;
 
 use lazy_static::lazy_static;
 use regex::Regex;
-
+use serde::{Deserialize, Serialize};
+
+use super::assets::AssetKind;
+use super::cache;
+use super::cache::CacheTracker;
+use super::check::ProblemKind;
+use super::highlight;
+use super::images::{ImageOverrides, ResponsiveImage};
+use super::math;
 use super::website;
 use super::website::BlogElement;
 use super::Mode;
 
 use orgize::elements;
 use orgize::export::{DefaultHtmlHandler, HtmlEscape, HtmlHandler};
-use orgize::{Element, Org};
+use orgize::{Element, Event, Org};
 
 #[derive(Debug)]
 pub enum HTMLExportError {
     UTF8(FromUtf8Error),
     IO(IOError),
     UnresolvedLink(String),
-    UnknownLinkFileEnding(String),
+    UnresolvedHeadline(String),
+    InvalidAssetPath(String),
     AttributeInsertError(String),
+    Highlight(highlight::Error),
+    Preamble(website::OrgFileError),
+}
+
+impl std::fmt::Display for HTMLExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UTF8(err) => write!(f, "Rendered html is not valid utf-8: {}", err),
+            Self::IO(err) => write!(f, "{}", err),
+            Self::UnresolvedLink(link) => write!(f, "Unable to resolve link to {}", link),
+            Self::UnresolvedHeadline(link) => {
+                write!(f, "Link to {} points to a headline that doesn't exist", link)
+            }
+            Self::InvalidAssetPath(link) => write!(
+                f,
+                "Linked file {} has to be in the folder of the org file or one of its subfolders",
+                link
+            ),
+            Self::AttributeInsertError(err) => write!(f, "{}", err),
+            Self::Highlight(err) => write!(f, "Unable to highlight source block: {}", err),
+            Self::Preamble(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for HTMLExportError {}
+
+impl HTMLExportError {
+    /// The text in the org source that caused the error, used to find its location
+    fn source_text(&self) -> Option<&str> {
+        match self {
+            Self::UnresolvedLink(link)
+            | Self::UnresolvedHeadline(link)
+            | Self::InvalidAssetPath(link) => Some(link),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct SerializationError {
     file: String,
+    /// The line and column in the org file, if known
+    position: Option<(usize, usize)>,
     err: HTMLExportError,
 }
 
+impl SerializationError {
+    pub fn new(file: &website::OrgFile, err: HTMLExportError) -> Self {
+        SerializationError {
+            file: file.path.to_str().unwrap().to_string(),
+            position: err
+                .source_text()
+                .and_then(|text| website::locate(&file.contents, text)),
+            err,
+        }
+    }
+}
+
+impl std::fmt::Display for SerializationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(f, "{}:{}:{}: {}", self.file, line, column, self.err),
+            None => write!(f, "{}: {}", self.file, self.err),
+        }
+    }
+}
+
+impl std::error::Error for SerializationError {}
+
 impl From<FromUtf8Error> for HTMLExportError {
     fn from(err: FromUtf8Error) -> Self {
         Self::UTF8(err)
@@ -40,15 +113,43 @@ impl From<IOError> for HTMLExportError {
     }
 }
 
+impl From<highlight::Error> for HTMLExportError {
+    fn from(err: highlight::Error) -> Self {
+        Self::Highlight(err)
+    }
+}
+
+/// Split the value of `#+attr_html` into its `:key value` pairs, as in
+/// `:widths 320 640 :formats webp`. Returns None if it doesn't start with a key
+fn split_options(value: &str) -> Option<Vec<(&str, String)>> {
+    let mut options: Vec<(&str, Vec<&str>)> = Vec::new();
+    for token in value.split_whitespace() {
+        match token.strip_prefix(':') {
+            Some(key) if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphabetic()) => {
+                options.push((key, Vec::new()))
+            }
+            _ => options.last_mut()?.1.push(token),
+        }
+    }
+    Some(
+        options
+            .into_iter()
+            .map(|(key, value)| (key, value.join(" ")))
+            .collect(),
+    )
+}
+
 enum ResolvedInternalLink {
     Post(String),
-    Image(String),
+    Asset(String, AssetKind),
 }
 
 #[derive(Default)]
 pub struct Attributes {
     pub caption: Option<String>,
     style: HashMap<String, String>,
+    /// Options for the variants of an image, e.g. `#+attr_html: :widths 320 640`
+    image: ImageOverrides,
     /// A flag to ignore attributes. This is used to ignore attributes in the preamble
     ignore_insert: bool,
 }
@@ -59,28 +160,26 @@ impl Attributes {
             return Ok(true);
         }
 
-        match attribute.key.as_ref() {
+        match attribute.key.to_uppercase().as_str() {
             "ATTR_HTML" => {
                 lazy_static! {
                     static ref CSS_RE: Regex =
-                        Regex::new(":style (?P<attr>[[:alpha:]]+): (?P<value>[[:word:]%]+);?$")
-                            .unwrap();
+                        Regex::new("^(?P<attr>[[:alpha:]]+): (?P<value>[[:word:]%]+);?$").unwrap();
                 }
-                if CSS_RE
-                    .captures(&attribute.value)
-                    .and_then(|cap| {
-                        self.style.insert(
-                            cap.name("attr").unwrap().as_str().to_string(),
-                            cap.name("value").unwrap().as_str().to_string(),
-                        );
-                        Some(())
-                    })
-                    .is_none()
-                {
-                    return Err(format!(
-                        "Unable to handle HTML attribute `{}`",
-                        attribute.value
-                    ));
+                let unknown = || format!("Unable to handle HTML attribute `{}`", attribute.value);
+                let options = split_options(&attribute.value).ok_or_else(unknown)?;
+                for (key, value) in options {
+                    if key != "style" {
+                        if value.is_empty() || !self.image.insert(key, &value)? {
+                            return Err(unknown());
+                        }
+                        continue;
+                    }
+                    let cap = CSS_RE.captures(&value).ok_or_else(unknown)?;
+                    self.style.insert(
+                        cap.name("attr").unwrap().as_str().to_string(),
+                        cap.name("value").unwrap().as_str().to_string(),
+                    );
                 }
             }
             "CAPTION" => self.caption = Some(attribute.value.to_string()),
@@ -113,6 +212,108 @@ impl Attributes {
     }
 }
 
+/// The html id of a headline, which is used as target for links to it
+pub struct Anchor {
+    pub title: String,
+    pub custom_id: Option<String>,
+    pub id: String,
+}
+
+/// Collect the anchors of all headlines in an org file, in the order they appear in.
+/// The id is taken from the `:CUSTOM_ID:` property or created from the title
+pub fn headline_anchors(contents: &str) -> Vec<Anchor> {
+    let mut headlines = Vec::new();
+    for event in Org::parse(contents).iter() {
+        if let Event::Start(Element::Title(title)) = event {
+            let custom_id = title
+                .properties
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case("CUSTOM_ID"))
+                .map(|(_, value)| value.to_string());
+            headlines.push((title.raw.trim().to_string(), custom_id));
+        }
+    }
+
+    // custom ids are reserved first, so that no headline before them can get the same id
+    let mut used: HashSet<String> = headlines
+        .iter()
+        .filter_map(|(_, custom_id)| custom_id.clone())
+        .collect();
+
+    let mut anchors = Vec::new();
+    for (title, custom_id) in headlines {
+        let id = match &custom_id {
+            Some(id) => id.clone(),
+            None => {
+                let slug = match website::slugify(&title) {
+                    slug if slug.is_empty() => String::from("section"),
+                    slug => slug,
+                };
+                // headlines with the same title get a numbered suffix
+                let mut id = slug.clone();
+                let mut counter = 2;
+                while used.contains(&id) {
+                    id = format!("{}-{}", slug, counter);
+                    counter += 1;
+                }
+                used.insert(id.clone());
+                id
+            }
+        };
+
+        anchors.push(Anchor {
+            title,
+            custom_id,
+            id,
+        });
+    }
+    anchors
+}
+
+/// Find the anchor for the search option of a link, which is either `*Headline` or `#custom-id`
+fn find_anchor<'b>(anchors: &'b [Anchor], search: &str) -> Option<&'b Anchor> {
+    if let Some(id) = search.strip_prefix('#') {
+        anchors
+            .iter()
+            .find(|anchor| anchor.custom_id.as_deref() == Some(id))
+    } else if let Some(title) = search.strip_prefix('*') {
+        anchors.iter().find(|anchor| anchor.title == title.trim())
+    } else {
+        None
+    }
+}
+
+/// An entry of the table of contents of a post
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TocEntry {
+    pub title: String,
+    pub anchor: String,
+    pub level: usize,
+    pub children: Vec<TocEntry>,
+}
+
+impl TocEntry {
+    /// Add an entry as the child of the last entry with a lower level
+    fn insert(entries: &mut Vec<TocEntry>, entry: TocEntry) {
+        match entries.last_mut() {
+            Some(last) if last.level < entry.level => Self::insert(&mut last.children, entry),
+            _ => entries.push(entry),
+        }
+    }
+
+    /// Remove all entries below `depth`
+    pub fn limit(entries: &[TocEntry], depth: usize) -> Vec<TocEntry> {
+        entries
+            .iter()
+            .filter(|entry| entry.level <= depth)
+            .map(|entry| TocEntry {
+                children: Self::limit(&entry.children, depth),
+                ..entry.clone()
+            })
+            .collect()
+    }
+}
+
 #[derive(Default)]
 pub struct OrgHTMLHandler<'a> {
     website: Option<&'a website::Website>,
@@ -120,37 +321,114 @@ pub struct OrgHTMLHandler<'a> {
     fallback: DefaultHtmlHandler,
     attributes: Attributes,
     base_url: String,
-    image_deps: Vec<String>,
+    asset_deps: Vec<String>,
+    images: Vec<ResponsiveImage>,
+    link_deps: Vec<(PathBuf, String)>,
+    mathjax: bool,
     first_footnote: bool,
+    /// The anchors of all headlines in the post
+    anchors: Arc<Vec<Anchor>>,
+    /// The index of the anchor for the next headline
+    next_anchor: usize,
+    /// The number of the last line of the previous numbered source block, which `+n` continues
+    last_line: usize,
+    toc: Vec<TocEntry>,
+    /// If set, problems are collected here instead of aborting the rendering or printing a warning
+    problems: Option<Vec<(ProblemKind, String)>>,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct RenderResult {
     pub content: String,
-    pub image_deps: Vec<String>,
+    /// All linked files that are copied next to the page
+    pub asset_deps: Vec<String>,
+    /// The images with their size and the variants that are generated for them
+    pub images: Vec<ResponsiveImage>,
+    /// All linked org files with the url they resolved to
+    pub link_deps: Vec<(PathBuf, String)>,
+    /// Whether the file contains formulas that couldn't be converted to MathML,
+    /// which are rendered by MathJax in the browser instead
+    pub mathjax: bool,
+    /// The table of contents with all headlines of the file
+    pub toc: Vec<TocEntry>,
 }
 
 impl<'a> OrgHTMLHandler<'a> {
-    pub fn render_post<T: Mode>(
-        website: &website::Website,
-        post: &website::OrgFile,
-        mode: &T,
-    ) -> Result<RenderResult, HTMLExportError> {
-        let parser = Org::parse(&post.contents);
-        let mut handler = OrgHTMLHandler {
+    fn new<T: Mode>(website: &'a website::Website, post: &'a website::OrgFile, mode: &T) -> Self {
+        OrgHTMLHandler {
             website: Some(website),
             post: Some(post),
             fallback: DefaultHtmlHandler::default(),
             attributes: Attributes::none(),
             base_url: mode.base_url(),
-            image_deps: Vec::new(),
+            asset_deps: Vec::new(),
+            images: Vec::new(),
+            link_deps: Vec::new(),
+            mathjax: false,
             first_footnote: true,
-        };
+            anchors: post.anchors(),
+            next_anchor: 0,
+            last_line: 0,
+            toc: Vec::new(),
+            problems: None,
+        }
+    }
+
+    fn render(mut self) -> Result<(RenderResult, Option<Vec<(ProblemKind, String)>>), HTMLExportError> {
+        let parser = Org::parse(&self.post.unwrap().contents);
         let mut writer = Vec::new();
-        parser.write_html_custom(&mut writer, &mut handler)?;
-        Ok(RenderResult {
-            content: String::from_utf8(writer)?,
-            image_deps: handler.image_deps,
-        })
+        parser.write_html_custom(&mut writer, &mut self)?;
+        Ok((
+            RenderResult {
+                content: String::from_utf8(writer)?,
+                asset_deps: self.asset_deps,
+                images: self.images,
+                link_deps: self.link_deps,
+                mathjax: self.mathjax,
+                toc: self.toc,
+            },
+            self.problems,
+        ))
+    }
+
+    pub fn render_post<T: Mode>(
+        website: &website::Website,
+        post: &website::OrgFile,
+        mode: &T,
+    ) -> Result<RenderResult, HTMLExportError> {
+        Ok(OrgHTMLHandler::new(website, post, mode).render()?.0)
+    }
+
+    /// Render a post without stopping at broken links or invalid attributes
+    /// and return all problems that were found
+    pub fn check_post<T: Mode>(
+        website: &website::Website,
+        post: &website::OrgFile,
+        mode: &T,
+    ) -> Result<(RenderResult, Vec<(ProblemKind, String)>), HTMLExportError> {
+        let mut handler = OrgHTMLHandler::new(website, post, mode);
+        handler.problems = Some(Vec::new());
+        let (result, problems) = handler.render()?;
+        Ok((result, problems.unwrap_or_default()))
+    }
+
+    /// Record a problem that doesn't prevent the rendering
+    fn warn(&mut self, kind: ProblemKind, message: String) {
+        match &mut self.problems {
+            Some(problems) => problems.push((kind, message)),
+            None => println!("Warning: {}", message),
+        }
+    }
+
+    /// Record a problem that aborts the rendering unless problems are collected
+    fn fail(&mut self, kind: ProblemKind, err: HTMLExportError) -> Result<(), HTMLExportError> {
+        match &mut self.problems {
+            Some(problems) => {
+                problems.push((kind, err.to_string()));
+                Ok(())
+            }
+            None => Err(err),
+        }
     }
 
     /// return true if the fallback rendering should be used
@@ -159,58 +437,127 @@ impl<'a> OrgHTMLHandler<'a> {
         w: &mut W,
         link: &elements::Link,
     ) -> Result<bool, HTMLExportError> {
-        let mut link_it = link.path.split(":");
-        let link_type = link_it.next();
-        let link_path = link_it.next().unwrap();
-
-        if link_it.next().is_some() {
-            println!("Found link to a section ({}), which is not supported yet. The link will simply point to the file", link.path);
+        // links to headlines in the same file, e.g. [[*Headline]] or [[#custom-id]]
+        if link.path.starts_with('*') || link.path.starts_with('#') {
+            match find_anchor(&self.anchors, &link.path) {
+                None => {
+                    self.fail(
+                        ProblemKind::BrokenLink,
+                        HTMLExportError::UnresolvedHeadline(link.path.to_string()),
+                    )?;
+                    return Ok(true);
+                }
+                Some(anchor) => {
+                    let target = format!("#{}", anchor.id);
+                    let desc = link.desc.as_deref().unwrap_or(&anchor.title);
+                    write!(w, "<a href=\"{}\">{}</a>", HtmlEscape(&target), HtmlEscape(desc))?;
+                    return Ok(false);
+                }
+            }
         }
 
-        if link_type.is_none() {
-            println!(
-                "Warning: Link {} in {:?} has no type. It will not be resolved",
-                link.path,
-                self.post.unwrap().path
-            );
-            return Ok(true);
-        }
+        let (link_type, link_path) = match link.path.find(':') {
+            Some(index) => (&link.path[..index], &link.path[index + 1..]),
+            None => {
+                let message = format!(
+                    "Link {} in {:?} has no type. It will not be resolved",
+                    link.path,
+                    self.post.unwrap().path
+                );
+                self.warn(ProblemKind::UnsupportedLink, message);
+                return Ok(true);
+            }
+        };
 
-        match link_type.unwrap() {
+        match link_type {
             // external links don't need to be resolved
-            "https" | "http" | "mailto" => return Ok(true),
-            "file" => match self.resolve_link(&link_path)? {
-                ResolvedInternalLink::Post(target) => {
-                    write!(
-                        w,
-                        "<a href=\"{}\">{}</a>",
-                        HtmlEscape(&target),
-                        HtmlEscape(link.desc.as_ref().map_or(target.as_str(), |s| &s))
-                    )?;
+            "https" | "http" => {
+                if self.problems.is_some() && !is_valid_url(&link.path) {
+                    let message = format!("Link {} is not a valid url", link.path);
+                    self.warn(ProblemKind::InvalidUrl, message);
                 }
-                ResolvedInternalLink::Image(target) => {
-                    let alt = link.desc.as_ref().map(|s| s.as_ref());
-                    match &self.attributes.caption {
-                        None => {
-                            self.render_image(w, &target, alt, &self.attributes.get_inline_style())?
-                        }
-                        Some(c) => self.render_figure(w, &target, alt, &c)?,
-                    };
+                return Ok(true);
+            }
+            "mailto" => return Ok(true),
+            "file" => {
+                // file:path.org::*Headline or file:path.org::#custom-id
+                let (file, search) = match link_path.find("::") {
+                    Some(index) => (&link_path[..index], Some(&link_path[index + 2..])),
+                    None => (link_path, None),
+                };
+                match self.resolve_link(file, search) {
+                    Err(err) => {
+                        self.fail(ProblemKind::BrokenLink, err)?;
+                        return Ok(true);
+                    }
+                    Ok(ResolvedInternalLink::Post(target)) => {
+                        write!(
+                            w,
+                            "<a href=\"{}\">{}</a>",
+                            HtmlEscape(&target),
+                            HtmlEscape(link.desc.as_ref().map_or(target.as_str(), |s| &s))
+                        )?;
+                    }
+                    Ok(ResolvedInternalLink::Asset(target, AssetKind::Image { responsive })) => {
+                        let alt = link.desc.as_ref().map(|s| s.as_ref());
+                        match self.attributes.caption.clone() {
+                            None => {
+                                let style = self.attributes.get_inline_style();
+                                self.render_image(w, &target, alt, &style, responsive)?
+                            }
+                            Some(c) => self.render_figure(w, &target, alt, &c, responsive)?,
+                        };
+                    }
+                    Ok(ResolvedInternalLink::Asset(target, AssetKind::Video)) => {
+                        let desc = link.desc.as_ref().map_or("", |s| s);
+                        write!(
+                            w,
+                            "<video controls preload=\"metadata\" src=\"./{}\"{}>{}</video>",
+                            HtmlEscape(&target),
+                            self.attributes.get_inline_style(),
+                            HtmlEscape(desc)
+                        )?;
+                    }
+                    Ok(ResolvedInternalLink::Asset(target, AssetKind::Audio)) => {
+                        let desc = link.desc.as_ref().map_or("", |s| s);
+                        write!(
+                            w,
+                            "<audio controls preload=\"metadata\" src=\"./{}\"{}>{}</audio>",
+                            HtmlEscape(&target),
+                            self.attributes.get_inline_style(),
+                            HtmlEscape(desc)
+                        )?;
+                    }
+                    Ok(ResolvedInternalLink::Asset(target, AssetKind::Download)) => {
+                        // the path of assets is validated when resolving the link
+                        let name = Path::new(&target).file_name().unwrap().to_str().unwrap();
+                        write!(
+                            w,
+                            "<a href=\"./{}\" download>{}</a>",
+                            HtmlEscape(&target),
+                            HtmlEscape(link.desc.as_ref().map_or(name, |s| s))
+                        )?;
+                    }
                 }
-            },
+            }
             lt => {
-                println!(
-                    "Warning: Unknown link type {} in file {:?}. Link will not be resolved",
+                let message = format!(
+                    "Unknown link type {} in file {:?}. Link will not be resolved",
                     lt,
                     self.post.unwrap().path
                 );
+                self.warn(ProblemKind::UnsupportedLink, message);
                 return Ok(true);
             }
         };
         Ok(false)
     }
 
-    fn resolve_link(&mut self, link: &str) -> Result<ResolvedInternalLink, HTMLExportError> {
+    fn resolve_link(
+        &mut self,
+        link: &str,
+        search: Option<&str>,
+    ) -> Result<ResolvedInternalLink, HTMLExportError> {
         let website = self.website.unwrap();
         let post = self.post.unwrap();
 
@@ -219,42 +566,116 @@ impl<'a> OrgHTMLHandler<'a> {
                 let path = post.resolve_link(link);
                 match website.resolve_path(&path) {
                     None => Err(HTMLExportError::UnresolvedLink(link.to_string())),
-                    Some(p) => Ok(ResolvedInternalLink::Post(
-                        p.url(&website, self.base_url.clone()),
-                    )),
+                    Some(p) => {
+                        let mut url = p.url(&website, self.base_url.clone());
+                        match search {
+                            None => {}
+                            Some(search) if search.starts_with('*') || search.starts_with('#') => {
+                                match find_anchor(&p.anchors(), search) {
+                                    None => {
+                                        return Err(HTMLExportError::UnresolvedHeadline(
+                                            format!("{}::{}", link, search),
+                                        ))
+                                    }
+                                    Some(anchor) => url = url + "#" + &anchor.id,
+                                }
+                            }
+                            Some(search) => {
+                                let message = format!(
+                                    "Search option {} in link to {} is not supported. The link will simply point to the file",
+                                    search, link
+                                );
+                                self.warn(ProblemKind::UnsupportedLink, message);
+                            }
+                        }
+                        self.link_deps.push((path, url.clone()));
+                        Ok(ResolvedInternalLink::Post(url))
+                    }
                 }
             }
-            "png" | "jpeg" => {
-                self.image_deps.push(String::from(link));
-                Ok(ResolvedInternalLink::Image(link.to_string()))
+            _ => {
+                // linked files are copied to the same path relative to the page, which has to stay in its folder
+                let path = Path::new(link);
+                let outside = path.components().any(|component| {
+                    !matches!(component, Component::Normal(_) | Component::CurDir)
+                });
+                if outside || path.file_name().is_none() {
+                    return Err(HTMLExportError::InvalidAssetPath(link.to_string()));
+                }
+                self.asset_deps.push(String::from(link));
+                Ok(ResolvedInternalLink::Asset(link.to_string(), AssetKind::of(link)))
             }
-            _ => Err(HTMLExportError::UnknownLinkFileEnding(
-                post.path.to_str().unwrap().to_string(),
-            )),
         }
     }
 
     fn render_figure<W: Write>(
-        &self,
+        &mut self,
         w: &mut W,
         src: &str,
         alt: Option<&str>,
         caption: &str,
+        responsive: bool,
     ) -> Result<(), HTMLExportError> {
         write!(w, "<figure{}>", self.attributes.get_inline_style())?;
-        self.render_image(w, src, alt, " style=\"width: 100%\"")?;
+        self.render_image(w, src, alt, " style=\"width: 100%\"", responsive)?;
         write!(w, "<figcaption>{}</figcaption></figure>", caption)?;
 
         Ok(())
     }
 
+    /// Formulas are converted to MathML. The ones that can't be converted are written as they are
+    /// and rendered by MathJax, which the page then has to load
+    fn write_text<W: Write>(&mut self, w: &mut W, text: &str) -> Result<(), HTMLExportError> {
+        lazy_static! {
+            static ref LINEBREAKS: Regex = Regex::new("\\n").unwrap();
+        }
+        for segment in math::split(text) {
+            match segment {
+                math::Segment::Text(text) => {
+                    write!(w, "{}", LINEBREAKS.replace_all(text, "<br>\n"))?
+                }
+                math::Segment::Math {
+                    source,
+                    tex,
+                    display,
+                } => match tex.map(|tex| math::to_mathml(tex, display)) {
+                    Some(Ok(mathml)) => write!(w, "{}", mathml)?,
+                    _ => {
+                        self.mathjax = true;
+                        match tex {
+                            // MathJax doesn't recognize single dollars by default
+                            Some(tex) if source.starts_with('$') && !display => {
+                                write!(w, "\\({}\\)", tex)?
+                            }
+                            _ => write!(w, "{}", source)?,
+                        }
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Images are written with their size and the generated variants. Missing images are
+    /// reported when the post is written, so they are simply linked here
     fn render_image<W: Write>(
-        &self,
+        &mut self,
         w: &mut W,
         src: &str,
         alt: Option<&str>,
         style: &str,
+        responsive: bool,
     ) -> Result<(), HTMLExportError> {
+        let folder = self.post.unwrap().path.parent().unwrap_or(Path::new(""));
+        let config = &self.website.unwrap().config.images;
+        if responsive {
+            if let Some(image) = ResponsiveImage::new(folder, src, config, &self.attributes.image) {
+                image.write_html(w, alt, style)?;
+                self.images.push(image);
+                return Ok(());
+            }
+        }
+
         if let Some(desc) = alt {
             write!(
                 w,
@@ -272,27 +693,60 @@ impl<'a> OrgHTMLHandler<'a> {
 
 impl HtmlHandler<HTMLExportError> for OrgHTMLHandler<'_> {
     fn start<W: Write>(&mut self, mut w: W, element: &Element) -> Result<(), HTMLExportError> {
-        lazy_static! {
-            static ref LINEBREAKS: Regex = Regex::new("\\n").unwrap();
-        }
         match element {
             Element::Keyword(keyword) => match self.attributes.insert(keyword) {
-                Err(err) => return Err(HTMLExportError::AttributeInsertError(err)),
-                Ok(false) => println!(
-                    "Warning: Unhandled attribute `{}` in {:?}",
-                    keyword.key,
-                    self.post.unwrap().path
-                ),
+                Err(err) => self.fail(
+                    ProblemKind::InvalidAttribute,
+                    HTMLExportError::AttributeInsertError(err),
+                )?,
+                Ok(false) => {
+                    let message = format!(
+                        "Unhandled attribute `{}` in {:?}",
+                        keyword.key,
+                        self.post.unwrap().path
+                    );
+                    self.warn(ProblemKind::UnknownAttribute, message);
+                }
                 Ok(true) => {}
             },
-            Element::Text{value} => {
-                write!(w, "{}", LINEBREAKS.replace_all(value, "<br>\n"))?;
-            },
+            Element::Title(title) => {
+                let id = match self.anchors.get(self.next_anchor) {
+                    Some(anchor) => anchor.id.clone(),
+                    None => website::slugify(&title.raw),
+                };
+                self.next_anchor += 1;
+                write!(w, "<h{} id=\"{}\">", title.level.min(6), HtmlEscape(&id))?;
+                TocEntry::insert(
+                    &mut self.toc,
+                    TocEntry {
+                        title: title.raw.trim().to_string(),
+                        anchor: id,
+                        level: title.level,
+                        children: Vec::new(),
+                    },
+                );
+            }
+            Element::Text { value } => self.write_text(&mut w, value)?,
             Element::Link(link) => {
                 if self.write_link(&mut w, &link)? {
                     self.fallback.start(w, element)?;
                 }
             }
+            Element::SourceBlock(block) => {
+                let options = highlight::BlockOptions::parse(&block.arguments);
+                let first_line = options.first_line(self.last_line);
+                if let Some(first) = first_line {
+                    self.last_line = first + highlight::line_count(&block.contents) - 1;
+                }
+                write!(
+                    w,
+                    "{}",
+                    highlight::render_block(&block.contents, &block.language, first_line)?
+                )?;
+            }
+            Element::InlineSrc(src) => {
+                write!(w, "{}", highlight::render_inline(&src.body, &src.lang)?)?;
+            }
             Element::FnRef(fnref) => {
                 write!(
                     w,
@@ -336,6 +790,17 @@ impl HtmlHandler<HTMLExportError> for OrgHTMLHandler<'_> {
     }
 }
 
+/// A rough syntax check for http(s) urls, which only catches obvious mistakes like spaces or a missing host
+fn is_valid_url(url: &str) -> bool {
+    lazy_static! {
+        static ref URL_RE: Regex = Regex::new(
+            r"^https?://[[:alnum:]]([[:alnum:]-]*[[:alnum:]])?(\.[[:alnum:]]([[:alnum:]-]*[[:alnum:]])?)*(:[0-9]+)?([/?#][^[:space:]]*)?$"
+        )
+        .unwrap();
+    }
+    URL_RE.is_match(url)
+}
+
 #[derive(Default)]
 pub struct OrgExtractGenerator {
     written_length: usize,
@@ -358,12 +823,7 @@ impl OrgExtractGenerator {
     }
 
     pub fn generate(file: &website::OrgFile) -> Result<String, SerializationError> {
-        Self::generate_extract(file).or_else(|err| {
-            Err(SerializationError {
-                file: file.path.to_str().unwrap().to_string(),
-                err,
-            })
-        })
+        Self::generate_extract(file).or_else(|err| Err(SerializationError::new(file, err)))
     }
 
     fn write<W: Write>(&mut self, mut w: W, text: &str) -> Result<(), HTMLExportError> {
@@ -429,12 +889,47 @@ impl website::OrgFile {
         &self,
         website: &website::Website,
         mode: &T,
+        cache: &CacheTracker,
     ) -> Result<RenderResult, SerializationError> {
-        OrgHTMLHandler::render_post(website, self, mode).or_else(|err| {
-            Err(SerializationError {
-                file: self.path.to_str().unwrap().to_string(),
-                err,
-            })
-        })
+        let hash = cache::hash((&self.contents, mode.base_url(), &website.config.images));
+        if let Some(result) = cache.lookup_render(&self.path, hash, website, mode) {
+            return Ok(result);
+        }
+
+        let result = OrgHTMLHandler::render_post(website, self, mode)
+            .or_else(|err| Err(SerializationError::new(self, err)))?;
+        cache.store_render(&self.path, hash, &result);
+        Ok(result)
     }
 }
+
+#[test]
+fn test_split_options() {
+    assert_eq!(
+        split_options(":widths 320 640 :formats webp"),
+        Some(vec![("widths", String::from("320 640")), ("formats", String::from("webp"))])
+    );
+    assert_eq!(
+        split_options(":style width: 50%;"),
+        Some(vec![("style", String::from("width: 50%;"))])
+    );
+    assert_eq!(split_options("width: 50%"), None);
+}
+
+#[test]
+fn test_headline_anchors() {
+    let anchors = headline_anchors(
+        "* Intro\n* Details\n:PROPERTIES:\n:CUSTOM_ID: deep\n:END:\n* Intro\n",
+    );
+    let ids: Vec<&str> = anchors.iter().map(|a| a.id.as_str()).collect();
+    assert_eq!(ids, vec!["intro", "deep", "intro-2"]);
+
+    assert_eq!(find_anchor(&anchors, "*Details").map(|a| a.id.as_str()), Some("deep"));
+    assert_eq!(find_anchor(&anchors, "#deep").map(|a| a.title.as_str()), Some("Details"));
+    assert!(find_anchor(&anchors, "#intro").is_none());
+
+    // a custom id after a headline with the same slug keeps its id
+    let anchors = headline_anchors("* Setup\n* Other\n:PROPERTIES:\n:CUSTOM_ID: setup\n:END:\n");
+    let ids: Vec<&str> = anchors.iter().map(|a| a.id.as_str()).collect();
+    assert_eq!(ids, vec!["setup-2", "setup"]);
+}
@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+
+use chrono::naive::NaiveDate;
+
+use super::check::CheckMode;
+use super::website::{BlogElement, OrgFile, Website, WebsiteError};
+
+/// A page or post with a publishing date in the future
+pub struct ScheduledFile {
+    pub path: PathBuf,
+    pub title: String,
+    pub published: NaiveDate,
+}
+
+/// All files of the website at `website_path` that are published after `today`,
+/// ordered by their publishing date
+pub fn schedule(
+    website_path: &str,
+    today: Option<NaiveDate>,
+) -> Result<Vec<ScheduledFile>, WebsiteError> {
+    let website = Website::load::<CheckMode>(website_path, today)?;
+
+    let mut files: Vec<&OrgFile> = website.pages.values().collect();
+    for project in website.projects.values() {
+        files.push(&project.index);
+        files.extend(project.posts.values());
+    }
+
+    let mut scheduled: Vec<_> = files
+        .into_iter()
+        .filter(|file| file.scheduled)
+        .map(|file| ScheduledFile {
+            path: file.path.clone(),
+            title: file.title().to_string(),
+            published: file.published.unwrap(),
+        })
+        .collect();
+    scheduled.sort_by(|lhs, rhs| (lhs.published, &lhs.path).cmp(&(rhs.published, &rhs.path)));
+    Ok(scheduled)
+}
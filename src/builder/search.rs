@@ -0,0 +1,84 @@
+use std::fs;
+use std::io::Error as IOError;
+use std::path::Path;
+
+use serde::Serialize;
+
+use super::serialize::{SerializedPost, SerializedResult};
+
+pub const SEARCH_INDEX_FILE_NAME: &str = "search.json";
+
+#[derive(Serialize)]
+struct Entry {
+    title: String,
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subtitle: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    /// The text of the post without any markup
+    text: String,
+}
+
+/// The index for the client side search, containing all rendered posts
+#[derive(Default)]
+pub struct SearchIndex {
+    entries: Vec<Entry>,
+}
+
+/// Remove all html tags from `html` and collapse the whitespace
+fn plain_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            // tags are replaced by a space, so that the words of adjacent blocks stay separate
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    let text = text
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+impl SearchIndex {
+    pub fn insert(&mut self, post: &SerializedResult<SerializedPost>) {
+        self.entries.push(Entry {
+            title: post.elem.heading.to_string(),
+            url: post.url.clone(),
+            subtitle: post.elem.subtitle.map(String::from),
+            summary: post.elem.summary.clone(),
+            text: plain_text(&post.elem.content),
+        });
+    }
+
+    /// Write the index into the folder at `path` and return the name of the written file
+    pub fn write(mut self, path: &Path) -> Result<String, IOError> {
+        // the posts are rendered in parallel, so they are sorted for a stable output
+        self.entries.sort_by(|lhs, rhs| lhs.url.cmp(&rhs.url));
+        fs::write(
+            path.join(SEARCH_INDEX_FILE_NAME),
+            serde_json::to_string(&self.entries)?,
+        )?;
+        Ok(SEARCH_INDEX_FILE_NAME.to_string())
+    }
+}
+
+#[test]
+fn test_plain_text() {
+    assert_eq!(
+        plain_text("<p>Some <b>bold</b>\n text</p><p>&lt;tag&gt; &amp; more</p>"),
+        "Some bold text <tag> & more"
+    );
+}
@@ -1,5 +1,9 @@
+use super::cache::CacheTracker;
+use super::feed::FeedLink;
+use super::images::ResponsiveImage;
 use super::rendering;
 use super::rendering::OrgExtractGenerator;
+use super::search;
 use super::website;
 use super::website::{BlogElement, PostOrder};
 use super::Mode;
@@ -12,6 +16,12 @@ pub struct LayoutInfo {
     website_name: SerializedLink,
     #[serde(rename = "base-url")]
     base_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<String>,
+    #[serde(rename = "live-reload")]
+    live_reload: bool,
+    /// The website feeds, to be advertised with `<link rel="alternate">`
+    feeds: Vec<FeedLink>,
 }
 
 #[derive(PartialOrd, PartialEq, Eq, Ord)]
@@ -19,12 +29,13 @@ enum LinkType {
     WebsiteIndex = 0,
     Page = 1,
     Project = 2,
+    Tag = 3,
 }
 
 #[derive(Serialize)]
 pub struct SerializedLink {
-    target: String,
-    title: String,
+    pub target: String,
+    pub title: String,
     #[serde(skip_serializing)]
     // this enum is only used to sort the list of links
     link_type: LinkType,
@@ -38,7 +49,11 @@ pub struct SerializedPost<'a> {
     #[serde(rename = "last-edit")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub last_edit: Option<chrono::naive::NaiveDate>,
+    /// Set for posts with a publishing date in the future, which are only part of previews
+    pub scheduled: bool,
     pub content: String,
+    /// Whether the page has to load MathJax for formulas that weren't converted to MathML
+    pub mathjax: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub subtitle: Option<&'a str>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -46,11 +61,17 @@ pub struct SerializedPost<'a> {
     pub title: String,
     pub heading: &'a str,
     pub id: &'a str,
+    pub tags: Vec<SerializedLink>,
+    /// Only set if the table of contents is enabled with `#+options: toc:N`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub toc: Vec<rendering::TocEntry>,
 }
 
 pub struct SerializedResult<T: Serialize> {
     pub elem: T,
-    pub image_deps: Vec<String>,
+    pub asset_deps: Vec<String>,
+    /// The images with the variants that are generated for them
+    pub images: Vec<ResponsiveImage>,
     pub folder_in: String,
     pub folder_out: String,
     pub url: String,
@@ -60,8 +81,11 @@ pub struct SerializedResult<T: Serialize> {
 struct PostSummary<'a> {
     heading: &'a str,
     id: &'a str,
+    url: String,
+    tags: Vec<SerializedLink>,
     #[serde(skip_serializing_if = "Option::is_none")]
     published: Option<chrono::naive::NaiveDate>,
+    scheduled: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     subtitle: Option<&'a str>,
 }
@@ -73,17 +97,55 @@ pub struct SerializedProjectIndex<'a> {
     pub heading: String,
     pub description: &'a str,
     pub text: String,
+    mathjax: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     published: Option<chrono::naive::NaiveDate>,
     posts: Vec<PostSummary<'a>>,
 }
 
-impl<'a> From<&'a website::OrgFile> for PostSummary<'a> {
-    fn from(post: &'a website::OrgFile) -> Self {
+#[derive(Serialize)]
+struct TagSummary {
+    name: String,
+    url: String,
+    count: usize,
+}
+
+#[derive(Serialize)]
+pub struct SerializedTagIndex<'a> {
+    layout: &'a LayoutInfo,
+    pub title: String,
+    pub heading: &'static str,
+    tags: Vec<TagSummary>,
+}
+
+#[derive(Serialize)]
+pub struct SerializedTagList<'a> {
+    layout: &'a LayoutInfo,
+    pub title: String,
+    pub heading: String,
+    pub tag: &'a str,
+    posts: Vec<PostSummary<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct SerializedSearch<'a> {
+    layout: &'a LayoutInfo,
+    pub title: String,
+    pub heading: &'static str,
+    /// The url of the search index, which is loaded by the search script
+    #[serde(rename = "index-url")]
+    index_url: String,
+}
+
+impl<'a> PostSummary<'a> {
+    fn new<T: Mode>(post: &'a website::OrgFile, website: &website::Website, mode: &T) -> Self {
         PostSummary {
             heading: post.title(),
             id: post.id(),
+            url: post.url(website, mode.base_url()),
+            tags: SerializedLink::from_tags(post, mode),
             published: post.published,
+            scheduled: post.scheduled,
             subtitle: post.from_preamble("subtitle"),
         }
     }
@@ -104,6 +166,19 @@ impl SerializedLink {
     }
 }
 
+impl SerializedLink {
+    fn from_tags<TMode: Mode>(post: &website::OrgFile, mode: &TMode) -> Vec<Self> {
+        post.tags
+            .iter()
+            .map(|tag| SerializedLink {
+                target: mode.base_url() + &website::tag_url(tag),
+                title: tag.clone(),
+                link_type: LinkType::Tag,
+            })
+            .collect()
+    }
+}
+
 impl LayoutInfo {
     pub fn new<T: Mode>(website: &website::Website, mode: &T) -> Self {
         let mut header = Vec::new();
@@ -135,6 +210,13 @@ impl LayoutInfo {
                 LinkType::WebsiteIndex,
             ),
             base_url: mode.base_url(),
+            author: website.config.author.name.clone(),
+            live_reload: mode.live_reload(),
+            feeds: if T::include_feeds() {
+                FeedLink::for_website(website, mode)
+            } else {
+                Vec::new()
+            },
         }
     }
 }
@@ -144,8 +226,9 @@ impl website::Website {
         &'a self,
         mode: &T,
         layout: &'a LayoutInfo,
+        cache: &CacheTracker,
     ) -> Result<SerializedResult<SerializedPost<'a>>, rendering::SerializationError> {
-        self.index.serialize(self, mode, layout)
+        self.index.serialize(self, mode, layout, cache)
     }
 }
 
@@ -172,29 +255,39 @@ impl website::Project {
         website: &'a website::Website,
         mode: &T,
         layout: &'a LayoutInfo,
+        cache: &CacheTracker,
     ) -> Result<SerializedResult<SerializedProjectIndex<'a>>, rendering::SerializationError> {
-        let mut posts: Vec<PostSummary> = self.posts.values().map(|p| p.into()).collect();
+        let mut posts: Vec<PostSummary> = self
+            .posts
+            .values()
+            .map(|p| PostSummary::new(p, website, mode))
+            .collect();
 
+        // the order is validated when loading the project
         match self
             .index
             .parse_from_preamble::<PostOrder>("order")
+            .ok()
+            .flatten()
             .unwrap_or_default()
         {
             PostOrder::NewestFirst => posts.sort_by(sort_by_published),
             PostOrder::ById => posts.sort_by(sort_by_id),
         };
-        let index = self.index.serialize(website, mode, layout)?;
+        let index = self.index.serialize(website, mode, layout, cache)?;
         Ok(SerializedResult {
             elem: SerializedProjectIndex {
                 layout,
                 posts,
                 text: index.elem.content,
-                title: self.title().to_string() + " | Johannes Huwald",
+                mathjax: index.elem.mathjax,
+                title: website.config.format_title(self.title()),
                 heading: self.title().to_string(),
                 description: self.description(),
                 published: self.index.published,
             },
-            image_deps: index.image_deps,
+            asset_deps: index.asset_deps,
+            images: index.images,
             folder_in: index.folder_in,
             folder_out: index.folder_out,
             url: self.url(&website, mode.base_url()),
@@ -208,20 +301,28 @@ impl website::OrgFile {
         website: &'a website::Website,
         mode: &T,
         layout: &'a LayoutInfo,
+        cache: &CacheTracker,
     ) -> Result<SerializedResult<SerializedPost<'a>>, rendering::SerializationError> {
-        let rr = self.render_html(website, mode)?;
+        self.check_required_fields().map_err(|err| {
+            rendering::SerializationError::new(self, rendering::HTMLExportError::Preamble(err))
+        })?;
+        let rr = self.render_html(website, mode, cache)?;
         let mut folder_in = self.path.clone();
         folder_in.pop();
         let folder_in = folder_in.to_str().unwrap().to_string();
 
-        let mut summary = self.parse_from_preamble::<String>("summary");
+        let mut summary = self.from_preamble("summary").map(String::from);
+        let toc = self
+            .toc_depth()
+            .map_or(Vec::new(), |depth| rendering::TocEntry::limit(&rr.toc, depth));
 
         if summary.is_none() && self.post_type == website::PostType::Mini {
             summary = Some(OrgExtractGenerator::generate(self)?);
         }
 
         Ok(SerializedResult {
-            image_deps: rr.image_deps,
+            asset_deps: rr.asset_deps,
+            images: rr.images,
             folder_in,
             folder_out: String::new(),
             url: self.url(&website, mode.base_url()),
@@ -230,12 +331,94 @@ impl website::OrgFile {
                 summary,
                 published: self.published,
                 last_edit: self.last_edit,
+                scheduled: self.scheduled,
                 content: rr.content,
+                mathjax: rr.mathjax,
                 subtitle: self.from_preamble("subtitle"),
-                title: self.title().to_string() + " | Johannes Huwald",
+                title: website.config.format_title(self.title()),
                 heading: self.title(),
                 id: self.id(),
+                tags: SerializedLink::from_tags(self, mode),
+                toc,
             },
         })
     }
 }
+
+/// Serialize the index of all tags and the listing pages for each tag
+pub fn serialize_tags<'a, T: Mode>(
+    website: &'a website::Website,
+    mode: &T,
+    layout: &'a LayoutInfo,
+) -> (
+    SerializedResult<SerializedTagIndex<'a>>,
+    Vec<SerializedResult<SerializedTagList<'a>>>,
+) {
+    let mut tags = Vec::new();
+    let mut lists = Vec::new();
+
+    for (slug, (tag, posts)) in website.posts_by_tag() {
+        let url = mode.base_url() + "/tags/" + &slug;
+        tags.push(TagSummary {
+            name: tag.to_string(),
+            url: url.clone(),
+            count: posts.len(),
+        });
+
+        let mut posts: Vec<PostSummary> = posts
+            .into_iter()
+            .map(|p| PostSummary::new(p, website, mode))
+            .collect();
+        posts.sort_by(sort_by_published);
+
+        lists.push(SerializedResult {
+            elem: SerializedTagList {
+                layout,
+                title: website.config.format_title(tag),
+                heading: tag.to_string(),
+                tag,
+                posts,
+            },
+            asset_deps: Vec::new(),
+            images: Vec::new(),
+            folder_in: String::new(),
+            folder_out: String::new(),
+            url,
+        });
+    }
+
+    let index = SerializedResult {
+        elem: SerializedTagIndex {
+            layout,
+            title: website.config.format_title("Tags"),
+            heading: "Tags",
+            tags,
+        },
+        asset_deps: Vec::new(),
+        images: Vec::new(),
+        folder_in: String::new(),
+        folder_out: String::new(),
+        url: mode.base_url() + "/tags",
+    };
+    (index, lists)
+}
+
+pub fn serialize_search<'a, T: Mode>(
+    website: &website::Website,
+    mode: &T,
+    layout: &'a LayoutInfo,
+) -> SerializedResult<SerializedSearch<'a>> {
+    SerializedResult {
+        elem: SerializedSearch {
+            layout,
+            title: website.config.format_title("Search"),
+            heading: "Search",
+            index_url: mode.base_url() + "/" + search::SEARCH_INDEX_FILE_NAME,
+        },
+        asset_deps: Vec::new(),
+        images: Vec::new(),
+        folder_in: String::new(),
+        folder_out: String::new(),
+        url: mode.base_url() + "/search",
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs::File;
+use std::io::{Error as IOError, Write};
+use std::path::Path;
+
+use chrono::naive::NaiveDate;
+
+use super::website::{BlogElement, OrgFile, Website};
+use super::Mode;
+
+pub const SITEMAP_FILE_NAME: &str = "sitemap.xml";
+pub const ROBOTS_FILE_NAME: &str = "robots.txt";
+
+struct Entry {
+    url: String,
+    lastmod: Option<NaiveDate>,
+}
+
+impl Entry {
+    fn from_file<TElem: BlogElement, TMode: Mode>(
+        elem: &TElem,
+        file: &OrgFile,
+        website: &Website,
+        mode: &TMode,
+    ) -> Self {
+        Entry {
+            url: elem.url(website, mode.base_url()),
+            lastmod: file.last_edit.or(file.published),
+        }
+    }
+}
+
+/// Escape text for xml content
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// All urls of the website, sorted so that the output is stable between builds.
+/// The tag pages are only included if `tags` is set
+fn collect_entries<TMode: Mode>(website: &Website, mode: &TMode, tags: bool) -> Vec<Entry> {
+    let mut entries = vec![Entry::from_file(website, &website.index, website, mode)];
+
+    for page in website.pages.values() {
+        entries.push(Entry::from_file(page, page, website, mode));
+    }
+
+    for project in website.projects.values() {
+        entries.push(Entry::from_file(project, &project.index, website, mode));
+        for post in project.posts.values() {
+            entries.push(Entry::from_file(post, post, website, mode));
+        }
+    }
+
+    let tags = if tags { website.posts_by_tag() } else { Default::default() };
+    if !tags.is_empty() {
+        entries.push(Entry {
+            url: mode.base_url() + "/tags",
+            lastmod: None,
+        });
+    }
+    for (slug, (_, posts)) in tags {
+        entries.push(Entry {
+            url: mode.base_url() + "/tags/" + &slug,
+            lastmod: posts.iter().filter_map(|p| p.last_edit.or(p.published)).max(),
+        });
+    }
+
+    entries.sort_by(|lhs, rhs| lhs.url.cmp(&rhs.url));
+    entries
+}
+
+/// Write `sitemap.xml` and, if enabled in the config, a `robots.txt` referencing it
+/// into the folder at `path`. Returns the names of the written files
+pub fn write<TMode: Mode>(
+    website: &Website,
+    mode: &TMode,
+    path: &Path,
+    tags: bool,
+) -> Result<Vec<String>, IOError> {
+    let mut out = File::create(path.join(SITEMAP_FILE_NAME))?;
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        out,
+        "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">"
+    )?;
+    for entry in collect_entries(website, mode, tags) {
+        writeln!(out, "  <url>")?;
+        writeln!(out, "    <loc>{}</loc>", escape(&entry.url))?;
+        if let Some(date) = entry.lastmod {
+            writeln!(out, "    <lastmod>{}</lastmod>", date)?;
+        }
+        writeln!(out, "  </url>")?;
+    }
+    writeln!(out, "</urlset>")?;
+
+    let mut written = vec![SITEMAP_FILE_NAME.to_string()];
+    if website.config.sitemap.robots {
+        let mut out = File::create(path.join(ROBOTS_FILE_NAME))?;
+        writeln!(out, "User-agent: *")?;
+        writeln!(out, "Allow: /")?;
+        writeln!(out)?;
+        writeln!(out, "Sitemap: {}/{}", mode.base_url(), SITEMAP_FILE_NAME)?;
+        written.push(ROBOTS_FILE_NAME.to_string());
+    }
+    Ok(written)
+}
@@ -1,35 +1,131 @@
-use std::fs;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
 use std::io::{Error as IOError, Write};
+use std::path::{Path, PathBuf};
 
 use chrono::naive::NaiveDate;
 use handlebars::{
-    Context, Handlebars, Helper, HelperResult, JsonRender, Output, RenderContext, TemplateFileError,
+    Context, Handlebars, Helper, HelperDef, HelperResult, JsonRender, Output, RenderContext,
+    TemplateFileError,
 };
 use serde::ser::Serialize;
 
-use super::fileutil::copy_folder_recursively;
+use super::cache;
+use super::fileutil::find_files_recursively;
 use super::website::ProjectType;
 
-fn render_date(
-    h: &Helper,
-    _: &Handlebars,
-    _: &Context,
-    _rc: &mut RenderContext,
-    out: &mut dyn Output,
-) -> HelperResult {
-    let param = h.param(0).unwrap();
-
-    let date = param.value().render();
-    let date = NaiveDate::parse_from_str(date.as_ref(), "%Y-%m-%d").unwrap();
-    out.write(&date.format("%A, %d. %B %Y").to_string())?;
-    Ok(())
+mod manifest;
+
+use manifest::{Assets, ThemeManifest, MANIFEST_FILE_NAME};
+
+thread_local! {
+    /// The assets used by the template that is currently rendered on this thread
+    static USED_ASSETS: RefCell<BTreeSet<String>> = RefCell::new(BTreeSet::new());
+}
+
+/// The templates pages are rendered with, which every theme has to contain
+const REQUIRED_TEMPLATES: &[&str] = &[
+    "layout",
+    "page",
+    "post",
+    "projects/catalogue",
+    "projects/multi",
+];
+/// Templates that are only used if the theme contains them
+const OPTIONAL_TEMPLATES: &[&str] = &["search", "tags/index", "tags/list"];
+/// Every `.hbs` file in this folder is registered as partial, named after its path relative to it
+const PARTIALS_FOLDER: &str = "partials";
+/// The format of the `date` helper, unless the theme declares its own
+const DATE_FORMAT: &str = "%A, %d. %B %Y";
+
+/// `{{date published}}` formats a date from the preamble. Themes can declare further
+/// date helpers with other formats
+struct DateHelper {
+    format: String,
+}
+
+impl HelperDef for DateHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let param = h.param(0).ok_or_else(|| {
+            handlebars::RenderError::new(format!("The {} helper requires a date", h.name()))
+        })?;
+
+        let date = param.value().render();
+        let date = NaiveDate::parse_from_str(date.as_ref(), "%Y-%m-%d").or_else(|err| {
+            Err(handlebars::RenderError::new(format!(
+                "Invalid date `{}` passed to the {} helper: {}",
+                date,
+                h.name(),
+                err
+            )))
+        })?;
+        out.write(&date.format(&self.format).to_string())?;
+        Ok(())
+    }
+}
+
+/// `{{asset "css/style.css"}}` returns the url of a file declared in the manifest
+/// and marks it as used by the current page
+struct AssetHelper {
+    assets: Option<Assets>,
+}
+
+impl HelperDef for AssetHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let path = h
+            .param(0)
+            .ok_or_else(|| handlebars::RenderError::new("The asset helper requires a path"))?
+            .value()
+            .render();
+        let (asset, output) = self
+            .assets
+            .as_ref()
+            .and_then(|assets| assets.resolve(&path))
+            .ok_or_else(|| {
+                handlebars::RenderError::new(format!(
+                    "Asset `{}` is not declared in {}",
+                    path, MANIFEST_FILE_NAME
+                ))
+            })?;
+
+        USED_ASSETS.with(|used| used.borrow_mut().insert(asset));
+        let base_url = ctx
+            .data()
+            .pointer("/layout/base-url")
+            .map_or(String::new(), |url| url.render());
+        out.write(&format!("{}/{}", base_url, output))?;
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
 pub enum ThemeError {
     Template(TemplateFileError),
     IO(IOError),
+    Manifest(toml::de::Error),
+    /// A file or folder from the manifest that can't be read
+    Asset(String, IOError),
+    /// The folder of the theme or one of its parents doesn't exist
+    NotFound(PathBuf),
+    /// A theme that is its own ancestor
+    InheritanceCycle(PathBuf),
+    /// A required template that neither the theme nor its parents define
+    MissingTemplate(&'static str),
 }
 
 #[derive(Debug)]
@@ -38,6 +134,36 @@ pub enum RenderError {
     IO(IOError),
 }
 
+impl std::fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Template(err) => write!(f, "Invalid template: {}", err),
+            Self::IO(err) => write!(f, "Unable to read theme: {}", err),
+            Self::Manifest(err) => write!(f, "Invalid {}: {}", MANIFEST_FILE_NAME, err),
+            Self::Asset(path, err) => write!(f, "Unable to read asset {}: {}", path, err),
+            Self::NotFound(path) => write!(f, "Theme folder {:?} doesn't exist", path),
+            Self::InheritanceCycle(path) => write!(f, "Theme {:?} inherits from itself", path),
+            Self::MissingTemplate(name) => write!(
+                f,
+                "The theme and its parents don't contain the template {}.hbs",
+                name
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Template(err) => write!(f, "{}", err),
+            Self::IO(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ThemeError {}
+impl std::error::Error for RenderError {}
+
 impl From<TemplateFileError> for ThemeError {
     fn from(err: TemplateFileError) -> Self {
         Self::Template(err)
@@ -50,6 +176,12 @@ impl From<IOError> for ThemeError {
     }
 }
 
+impl From<toml::de::Error> for ThemeError {
+    fn from(err: toml::de::Error) -> Self {
+        Self::Manifest(err)
+    }
+}
+
 impl From<handlebars::RenderError> for RenderError {
     fn from(err: handlebars::RenderError) -> Self {
         Self::Template(err)
@@ -65,12 +197,20 @@ impl From<IOError> for RenderError {
 pub struct Theme<'a> {
     templates: Handlebars<'a>,
     theme_dir: String,
+    /// The assets declared in the manifest. Themes without manifest copy all of their files
+    assets: Option<Assets>,
+    /// The hash over the contents of all templates and the names of the assets
+    templates_hash: u64,
 }
 
 pub enum TemplateType {
     Post,
     Project(ProjectType),
     Page,
+    TagIndex,
+    TagList,
+    /// Optional, the search page is only generated if the theme contains it
+    Search,
 }
 
 impl TemplateType {
@@ -82,63 +222,221 @@ impl TemplateType {
                 ProjectType::MultiPart => "projects/multi",
             },
             Self::Page => "page",
+            Self::TagIndex => "tags/index",
+            Self::TagList => "tags/list",
+            Self::Search => "search",
         }
     }
 }
 
+/// The folders of a theme and all of its parents with their manifests, starting with the theme itself
+fn theme_chain(path: &Path) -> Result<Vec<(PathBuf, Option<ThemeManifest>)>, ThemeError> {
+    let mut chain = Vec::new();
+    let mut visited = Vec::new();
+    let mut next = Some(path.to_path_buf());
+    while let Some(dir) = next {
+        if !dir.is_dir() {
+            return Err(ThemeError::NotFound(dir));
+        }
+        let canonical = dir.canonicalize()?;
+        if visited.contains(&canonical) {
+            return Err(ThemeError::InheritanceCycle(dir));
+        }
+        visited.push(canonical);
+
+        let manifest = ThemeManifest::load(&dir)?;
+        next = manifest.as_ref().and_then(|manifest| manifest.parent_dir(&dir));
+        chain.push((dir, manifest));
+    }
+    Ok(chain)
+}
+
+/// The folders of the theme at `path` and of all its parents
+pub fn theme_folders(path: &str) -> Result<Vec<PathBuf>, ThemeError> {
+    Ok(theme_chain(Path::new(path))?
+        .into_iter()
+        .map(|(dir, _)| dir)
+        .collect())
+}
+
+/// Register a template or partial and add its contents to `contents`. Templates that were
+/// registered before with the same name are replaced
+fn register_file(
+    templates: &mut Handlebars,
+    contents: &mut Vec<(String, String)>,
+    name: &str,
+    file: &Path,
+    partial: bool,
+) -> Result<(), ThemeError> {
+    let content = std::fs::read_to_string(file)
+        .map_err(|err| TemplateFileError::IOError(err, name.to_string()))?;
+    if partial {
+        templates.register_partial(name, &content)
+    } else {
+        templates.register_template_string(name, &content)
+    }
+    .map_err(TemplateFileError::from)?;
+    contents.push((name.to_string(), content));
+    Ok(())
+}
+
 impl<'a> Theme<'a> {
     pub fn load(path: &str) -> Result<Self, ThemeError> {
+        let chain = theme_chain(Path::new(path))?;
+        let no_manifest = ThemeManifest::default();
         let mut templates = Handlebars::new();
-        for template in [
-            "layout",
-            "page",
-            "post",
-            "projects/catalogue",
-            "projects/multi",
-        ]
-        .iter()
+        let mut contents = Vec::new();
+        let mut date_formats = BTreeMap::new();
+        date_formats.insert(String::from("date"), String::from(DATE_FORMAT));
+
+        // parents are registered first, so that their children can replace everything
+        for (dir, manifest) in chain.iter().rev() {
+            let manifest = manifest.as_ref().unwrap_or(&no_manifest);
+            for name in REQUIRED_TEMPLATES.iter().chain(OPTIONAL_TEMPLATES) {
+                let file = dir.join(format!("{}.hbs", name));
+                if !manifest.templates.contains_key(*name) && file.is_file() {
+                    register_file(&mut templates, &mut contents, name, &file, false)?;
+                }
+            }
+            for (name, file) in manifest.templates.iter() {
+                register_file(&mut templates, &mut contents, name, &dir.join(file), false)?;
+            }
+
+            if dir.join(PARTIALS_FOLDER).is_dir() {
+                let mut partials = find_files_recursively(dir, Path::new(PARTIALS_FOLDER))?;
+                partials.sort();
+                for file in partials {
+                    if file.extension().map_or(true, |extension| extension != "hbs") {
+                        continue;
+                    }
+                    let name = file.strip_prefix(PARTIALS_FOLDER).unwrap().with_extension("");
+                    let name = name
+                        .iter()
+                        .map(|part| part.to_str().unwrap())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    register_file(&mut templates, &mut contents, &name, &dir.join(&file), true)?;
+                }
+            }
+            for (name, file) in manifest.partials.iter() {
+                register_file(&mut templates, &mut contents, name, &dir.join(file), true)?;
+            }
+
+            for (name, helper) in manifest.helpers.iter() {
+                date_formats.insert(name.clone(), helper.date_format.clone());
+            }
+        }
+
+        if let Some(name) = REQUIRED_TEMPLATES
+            .iter()
+            .find(|name| !templates.has_template(name))
         {
-            let filename = format!("{}/{}.hbs", path, template);
-            templates.register_template_file(template, filename)?;
+            return Err(ThemeError::MissingTemplate(name));
         }
 
-        templates.register_helper("date", Box::new(render_date));
+        // themes without manifest can't declare assets, so all of their files are copied
+        let assets = match chain[0].1 {
+            None => None,
+            Some(_) => {
+                let mut assets = Assets::default();
+                for (dir, manifest) in chain.iter() {
+                    if let Some(manifest) = manifest {
+                        assets.add(dir, &manifest.assets)?;
+                    }
+                }
+                Some(assets)
+            }
+        };
+
+        for (name, format) in date_formats.iter() {
+            templates.register_helper(
+                name,
+                Box::new(DateHelper {
+                    format: format.clone(),
+                }),
+            );
+        }
+        templates.register_helper(
+            "asset",
+            Box::new(AssetHelper {
+                assets: assets.clone(),
+            }),
+        );
 
         Ok(Theme {
             templates,
             theme_dir: path.into(),
+            templates_hash: cache::hash((
+                contents,
+                date_formats,
+                assets.as_ref().map(|assets| assets.hash()),
+            )),
+            assets,
         })
     }
 
-    pub fn copy_files(&self, output_folder_path: &str) -> Result<(), IOError> {
-        copy_folder_recursively(
-            self.theme_dir.to_string() + "/css",
-            String::from(output_folder_path) + "/css",
-        )?;
+    pub fn has_template(&self, template: &TemplateType) -> bool {
+        self.templates.has_template(template.to_template_name())
+    }
 
-        copy_folder_recursively(
-            self.theme_dir.to_string() + "/js",
-            String::from(output_folder_path) + "/js",
-        )?;
+    /// Whether the theme can render the tag index and the listings of each tag
+    pub fn has_tag_pages(&self) -> bool {
+        self.has_template(&TemplateType::TagIndex) && self.has_template(&TemplateType::TagList)
+    }
 
-        fs::copy(
-            self.theme_dir.to_string() + "/favicon.png",
-            output_folder_path.to_string() + "/favicon.png",
-        )?;
-        Ok(())
+    /// The source files of the used assets with the paths they are written to, relative to
+    /// the output folder. Themes without manifest always copy their css and js folders and the favicon
+    pub fn asset_files(&self, used: &BTreeSet<String>) -> Result<Vec<(PathBuf, String)>, IOError> {
+        let assets = match &self.assets {
+            Some(assets) => assets,
+            None => {
+                let theme_dir = Path::new(&self.theme_dir);
+                let mut files = find_files_recursively(theme_dir, Path::new("css"))?;
+                files.extend(find_files_recursively(theme_dir, Path::new("js"))?);
+                files.push(PathBuf::from("favicon.png"));
+                return Ok(files
+                    .into_iter()
+                    .map(|file| {
+                        let output = file.to_str().unwrap().to_string();
+                        (theme_dir.join(file), output)
+                    })
+                    .collect());
+            }
+        };
+
+        let mut files = Vec::new();
+        for asset in used.iter() {
+            files.extend(assets.files(asset)?);
+        }
+        Ok(files)
+    }
+
+    /// A fingerprint for the rendered output of a template with the given data
+    pub fn fingerprint<TData: Serialize>(
+        &self,
+        template: &TemplateType,
+        data: &TData,
+    ) -> Result<u64, serde_json::Error> {
+        Ok(cache::hash((
+            self.templates_hash,
+            template.to_template_name(),
+            serde_json::to_string(data)?,
+        )))
     }
 
+    /// Render a template into `file` and return the assets it used
     pub fn render<TData: Serialize>(
         &self,
         file: &mut File,
         template: TemplateType,
         data: &TData,
-    ) -> Result<(), RenderError> {
+    ) -> Result<Vec<String>, RenderError> {
+        USED_ASSETS.with(|used| used.borrow_mut().clear());
         write!(
             file,
             "{}",
             self.templates.render(template.to_template_name(), data)?
         )?;
-        Ok(())
+        Ok(USED_ASSETS.with(|used| used.take().into_iter().collect()))
     }
 }
@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Error as IOError;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use super::super::cache;
+use super::super::fileutil::find_files_recursively;
+use super::ThemeError;
+
+/// The name of the manifest in the theme folder
+pub const MANIFEST_FILE_NAME: &str = "theme.toml";
+
+/// The contents of `theme.toml`
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeManifest {
+    /// The folder of the theme this one is based on, relative to this theme. Everything that
+    /// isn't defined by this theme is taken from it
+    pub parent: Option<String>,
+    /// The file of each template relative to the theme folder. Templates that aren't listed
+    /// are read from `<name>.hbs`
+    pub templates: BTreeMap<String, String>,
+    /// Partials outside of the `partials` folder, whose files are registered automatically
+    pub partials: BTreeMap<String, String>,
+    pub assets: AssetManifest,
+    pub helpers: BTreeMap<String, HelperManifest>,
+}
+
+/// The files of the theme that templates can refer to with `{{asset "path"}}`.
+/// Only the assets used by at least one page are copied into the output folder
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AssetManifest {
+    /// Files that are copied with the hash of their contents in the file name
+    pub files: Vec<String>,
+    /// Folders that are copied as a whole and with unchanged file names if any file in them
+    /// is used, e.g. for scripts that load other files relative to their own path
+    pub folders: Vec<String>,
+}
+
+/// A helper declared by the theme, e.g. `short-date = { date-format = "%d.%m.%Y" }`
+#[derive(Deserialize, Hash)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct HelperManifest {
+    /// Format a date from the preamble with this chrono format string
+    pub date_format: String,
+}
+
+impl ThemeManifest {
+    /// Returns None if the theme doesn't have a manifest
+    pub fn load(theme_dir: &Path) -> Result<Option<Self>, ThemeError> {
+        let path = theme_dir.join(MANIFEST_FILE_NAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(toml::from_str(&std::fs::read_to_string(path)?)?))
+    }
+
+    /// The folder of the parent theme, if there is one
+    pub fn parent_dir(&self, theme_dir: &Path) -> Option<PathBuf> {
+        self.parent.as_ref().map(|parent| theme_dir.join(parent))
+    }
+}
+
+/// Insert the first 8 hex digits of `hash` before the file ending of `path`
+fn hashed_name(path: &str, hash: u64) -> String {
+    let hash = format!("{:016x}", hash);
+    let name_start = path.rfind('/').map_or(0, |index| index + 1);
+    match path[name_start..].rfind('.') {
+        // hidden files like .htaccess don't have an ending
+        Some(index) if index > 0 => format!(
+            "{}.{}{}",
+            &path[..name_start + index],
+            &hash[..8],
+            &path[name_start + index..]
+        ),
+        _ => format!("{}.{}", path, &hash[..8]),
+    }
+}
+
+/// The declared assets of a theme and its parents with the paths they are written to
+#[derive(Clone, Default)]
+pub struct Assets {
+    /// The source and the path in the output folder of each file. The output path contains
+    /// the hash of the file contents
+    files: HashMap<String, (PathBuf, String)>,
+    /// The folders with the folder of the theme that declares them
+    folders: Vec<(String, PathBuf)>,
+}
+
+impl Assets {
+    /// Add the assets of a theme. Assets that were already added by a child theme are kept
+    pub fn add(&mut self, theme_dir: &Path, manifest: &AssetManifest) -> Result<(), ThemeError> {
+        for file in manifest.files.iter() {
+            if self.files.contains_key(file) {
+                continue;
+            }
+            let source = theme_dir.join(file);
+            let hash =
+                cache::hash_file(&source).map_err(|err| ThemeError::Asset(file.clone(), err))?;
+            self.files
+                .insert(file.clone(), (source, hashed_name(file, hash)));
+        }
+        for folder in manifest.folders.iter() {
+            if self.folders.iter().any(|(declared, _)| declared == folder) {
+                continue;
+            }
+            if !theme_dir.join(folder).is_dir() {
+                return Err(ThemeError::Asset(
+                    folder.clone(),
+                    IOError::from(std::io::ErrorKind::NotFound),
+                ));
+            }
+            self.folders.push((folder.clone(), theme_dir.to_path_buf()));
+        }
+        Ok(())
+    }
+
+    /// The asset that has to be copied if `path` is used and the path it is available at,
+    /// relative to the output folder. None if the path isn't declared in the manifest
+    pub fn resolve(&self, path: &str) -> Option<(String, String)> {
+        if let Some((_, output)) = self.files.get(path) {
+            return Some((path.to_string(), output.clone()));
+        }
+        let (folder, theme_dir) = self
+            .folders
+            .iter()
+            .find(|(folder, _)| Path::new(path).starts_with(folder))?;
+        if !theme_dir.join(path).is_file() {
+            return None;
+        }
+        Some((folder.clone(), path.to_string()))
+    }
+
+    /// The source files of a used asset with the paths they are written to
+    pub fn files(&self, asset: &str) -> Result<Vec<(PathBuf, String)>, IOError> {
+        if let Some((source, output)) = self.files.get(asset) {
+            return Ok(vec![(source.clone(), output.clone())]);
+        }
+        let theme_dir = match self.folders.iter().find(|(folder, _)| folder == asset) {
+            Some((_, theme_dir)) => theme_dir,
+            None => return Ok(Vec::new()),
+        };
+        Ok(find_files_recursively(theme_dir, Path::new(asset))?
+            .into_iter()
+            .map(|file| {
+                let output = file.to_str().unwrap().to_string();
+                (theme_dir.join(file), output)
+            })
+            .collect())
+    }
+
+    /// A hash over the output paths, which changes whenever a file is changed
+    pub fn hash(&self) -> u64 {
+        let mut files: Vec<_> = self.files.values().map(|(_, output)| output).collect();
+        files.sort();
+        let folders: Vec<_> = self.folders.iter().map(|(folder, _)| folder).collect();
+        cache::hash((files, folders))
+    }
+}
+
+#[test]
+fn test_hashed_name() {
+    assert_eq!(
+        hashed_name("css/style.css", 0x1234_5678_9abc_def0),
+        "css/style.12345678.css"
+    );
+    assert_eq!(hashed_name("favicon.png", 0xff), "favicon.00000000.png");
+    assert_eq!(hashed_name("js.d/LICENSE", 0xff), "js.d/LICENSE.00000000");
+}
@@ -1,19 +1,57 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::io::Error as IOError;
 use std::path::{Path, PathBuf};
 use std::string::FromUtf8Error;
+use std::sync::{Arc, Mutex};
 
+use chrono::naive::NaiveDate;
+use chrono::Utc;
 use orgize::{Element, Event, Org};
 use std::fs;
 
+use super::cache::CACHE_FILE_NAME;
+use super::config::{ConfigError, SiteConfig, CONFIG_FILE_NAME};
+use super::feed::FeedFormat;
+use super::redirect::RedirectFormat;
+use super::rendering::{headline_anchors, Anchor};
+use super::search::SEARCH_INDEX_FILE_NAME;
+use super::sitemap::{ROBOTS_FILE_NAME, SITEMAP_FILE_NAME};
 use super::Mode;
 
 #[derive(Debug)]
 pub enum WebsiteError {
     IO(IOError),
+    Config(ConfigError),
     Page(PathBuf, OrgFileError),
     Project(String, ProjectError),
-    DefaultProjectDoesNotExist,
+    MissingIndex(PathBuf),
+    DefaultProjectDoesNotExist(String),
+    DuplicateAlias(String),
+    Tag(PathBuf, TagError),
+    Alias(PathBuf, AliasError),
+    Url(PathBuf, UrlError),
+}
+
+/// Pages, project indices and posts with the same url would overwrite each other
+#[derive(Debug)]
+pub struct UrlError {
+    pub url: String,
+    pub others: Vec<PathBuf>,
+}
+
+/// Redirect pages are written to the aliases, so they can't use the url of another page
+#[derive(Debug)]
+pub enum AliasError {
+    UsedByFile(String, PathBuf),
+    Reserved(String),
+}
+
+/// Tag listings are written to the slug of the tag, so each tag needs a distinct, non-empty slug
+#[derive(Debug)]
+pub enum TagError {
+    EmptySlug(String),
+    SlugCollision(String, String),
 }
 
 #[derive(Debug)]
@@ -21,23 +59,191 @@ pub enum ProjectError {
     IO(IOError),
     DuplicateFileID(String),
     OrgFile(String, OrgFileError),
-    UnknownProjectType,
+    UnknownProjectType(String),
 }
 
 #[derive(Debug)]
 pub enum OrgFileError {
     IO(IOError),
     UTF8(FromUtf8Error),
-    Date(chrono::ParseError),
+    Date {
+        key: &'static str,
+        line: Option<usize>,
+        err: chrono::ParseError,
+    },
+    InvalidField {
+        key: String,
+        value: String,
+        line: Option<usize>,
+    },
     MissingRequiredField(&'static str),
 }
 
+impl fmt::Display for WebsiteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IO(err) => write!(f, "Unable to read the blog folder: {}", err),
+            Self::Config(err) => write!(f, "Invalid {}: {}", CONFIG_FILE_NAME, err),
+            Self::Page(path, err) => write!(f, "{}", err.with_location(path)),
+            Self::Project(id, err) => write!(f, "Unable to load project `{}`: {}", id, err),
+            Self::MissingIndex(path) => write!(
+                f,
+                "Found no website index (index.org) in {}",
+                path.display()
+            ),
+            Self::DefaultProjectDoesNotExist(id) => write!(
+                f,
+                "The default project `{}` does not exist. Set it with #+default_project in index.org",
+                id
+            ),
+            Self::DuplicateAlias(alias) => {
+                write!(f, "The alias `{}` is used by multiple files", alias)
+            }
+            Self::Tag(path, err) => write!(f, "{}: {}", path.display(), err),
+            Self::Alias(path, err) => write!(f, "{}: {}", path.display(), err),
+            Self::Url(path, err) => write!(f, "{}: {}", path.display(), err),
+        }
+    }
+}
+
+impl fmt::Display for AliasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UsedByFile(alias, path) => write!(
+                f,
+                "The alias `{}` is the url of {}",
+                alias,
+                path.display()
+            ),
+            Self::Reserved(alias) => write!(
+                f,
+                "The alias `{}` is the url of a page generated by silas",
+                alias
+            ),
+        }
+    }
+}
+
+impl fmt::Display for UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let others: Vec<_> = self.others.iter().map(|path| path.display().to_string()).collect();
+        write!(f, "The url `{}` is also used by {}", self.url, others.join(", "))
+    }
+}
+
+impl fmt::Display for TagError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::EmptySlug(tag) => write!(
+                f,
+                "The tag `{}` contains no letters or digits, so it has no url",
+                tag
+            ),
+            Self::SlugCollision(tag, other) => write!(
+                f,
+                "The tags `{}` and `{}` would both be listed at {}",
+                tag,
+                other,
+                tag_url(tag)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IO(err) => write!(f, "{}", err),
+            Self::DuplicateFileID(id) => {
+                write!(
+                f,
+                "There are multiple posts with the id `{}`. Rename one of them or use `#+url_style: nested` in the project index",
+                id
+            )
+            }
+            Self::OrgFile(path, err) => write!(f, "{}", err.with_location(Path::new(path))),
+            Self::UnknownProjectType(t) => write!(
+                f,
+                "Unknown project type `{}`. Expected `catalogue` or `multi`",
+                t
+            ),
+        }
+    }
+}
+
+impl fmt::Display for OrgFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IO(err) => write!(f, "{}", err),
+            Self::UTF8(err) => write!(f, "File is not valid utf-8: {}", err),
+            Self::Date { key, err, .. } => write!(
+                f,
+                "Invalid date for `{}`: {}. Expected <YYYY-MM-DD>",
+                key, err
+            ),
+            Self::InvalidField { key, value, .. } => {
+                write!(f, "Invalid value `{}` for `{}`", value, key)
+            }
+            Self::MissingRequiredField(key) => write!(f, "Missing required field `{}`", key),
+        }
+    }
+}
+
+impl std::error::Error for WebsiteError {}
+impl std::error::Error for AliasError {}
+impl std::error::Error for TagError {}
+impl std::error::Error for UrlError {}
+impl std::error::Error for ProjectError {}
+impl std::error::Error for OrgFileError {}
+
+impl OrgFileError {
+    /// The line in the org file the error occurred in, if known
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Self::Date { line, .. } | Self::InvalidField { line, .. } => *line,
+            _ => None,
+        }
+    }
+
+    /// Format the error as diagnostic, prefixed with `path` and the line if available
+    pub fn with_location(&self, path: &Path) -> String {
+        match self.line() {
+            Some(line) => format!("{}:{}: {}", path.display(), line, self),
+            None => format!("{}: {}", path.display(), self),
+        }
+    }
+}
+
+/// Find the 1-based line and column of the first occurrence of `needle` in `contents`
+pub fn locate(contents: &str, needle: &str) -> Option<(usize, usize)> {
+    let offset = contents.find(needle)?;
+    let before = &contents[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |index| index + 1) + 1;
+    Some((line, column))
+}
+
+/// The line of the keyword `#+key:` in an org file
+fn keyword_line(contents: &str, key: &str) -> Option<usize> {
+    let prefix = format!("#+{}:", key);
+    contents
+        .lines()
+        .position(|line| line.trim_start().to_lowercase().starts_with(&prefix))
+        .map(|index| index + 1)
+}
+
 impl From<IOError> for WebsiteError {
     fn from(err: IOError) -> Self {
         Self::IO(err)
     }
 }
 
+impl From<ConfigError> for WebsiteError {
+    fn from(err: ConfigError) -> Self {
+        Self::Config(err)
+    }
+}
+
 impl From<IOError> for ProjectError {
     fn from(err: IOError) -> Self {
         Self::IO(err)
@@ -56,12 +262,6 @@ impl From<FromUtf8Error> for OrgFileError {
     }
 }
 
-impl From<chrono::ParseError> for OrgFileError {
-    fn from(err: chrono::ParseError) -> Self {
-        Self::Date(err)
-    }
-}
-
 impl OrgFileError {
     pub fn to_project_error<T>(res: Result<T, Self>, path: &Path) -> Result<T, ProjectError> {
         res.or_else(|err| {
@@ -77,6 +277,9 @@ pub struct Website {
     pub projects: HashMap<String, Project>,
     pub pages: HashMap<PathBuf, OrgFile>,
     pub index: OrgFile,
+    pub config: SiteConfig,
+    /// The paths of the files by their aliases, which are urls relative to the base url
+    pub aliases: BTreeMap<String, PathBuf>,
 }
 
 pub struct Project {
@@ -84,6 +287,30 @@ pub struct Project {
     id: String,
     pub index: OrgFile,
     pub project_type: ProjectType,
+    /// The pattern for the urls of published posts from `#+permalink`, e.g. `/:year/:month/:slug`
+    permalink: Option<String>,
+    url_style: UrlStyle,
+}
+
+/// The placeholders that can be used in a permalink pattern
+const PERMALINK_PLACEHOLDERS: [&str; 5] = [":project", ":year", ":month", ":day", ":slug"];
+
+/// Whether `url` contains `.` or `..` segments, which would point outside of the folder
+/// the url is written to
+fn has_dot_segments(url: &str) -> bool {
+    url.split('/').any(|segment| segment == "." || segment == "..")
+}
+
+/// A permalink pattern has to start with a slash and contain `:slug`, so that the urls are unique.
+/// Placeholders have to be complete path segments
+fn is_valid_permalink(pattern: &str) -> bool {
+    pattern.starts_with('/')
+        && !has_dot_segments(pattern)
+        && pattern.split('/').any(|segment| segment == ":slug")
+        && pattern
+            .split('/')
+            .filter(|segment| segment.starts_with(':'))
+            .all(|segment| PERMALINK_PLACEHOLDERS.contains(&segment))
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -145,7 +372,33 @@ impl Default for PostOrder {
     }
 }
 
-#[derive(Clone)]
+/// How the urls of posts in subfolders of a project are created
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UrlStyle {
+    /// All posts are placed directly below the project, using their file name. The default value
+    Flat,
+    /// The subfolders of a post are part of its id and url, e.g. `/<project>/2021/post`
+    Nested,
+}
+
+impl std::str::FromStr for UrlStyle {
+    type Err = ();
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "flat" => Ok(Self::Flat),
+            "nested" => Ok(Self::Nested),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Default for UrlStyle {
+    fn default() -> Self {
+        UrlStyle::Flat
+    }
+}
+
 pub struct OrgFile {
     id: String,
     preamble: HashMap<String, String>,
@@ -153,8 +406,14 @@ pub struct OrgFile {
     pub contents: String,
     pub published: Option<chrono::naive::NaiveDate>,
     pub last_edit: Option<chrono::naive::NaiveDate>,
+    /// Whether the publishing date is in the future. Scheduled files are only part of previews
+    pub scheduled: bool,
+    /// The tags from `#+tags` and `#+filetags`, in lowercase
+    pub tags: Vec<String>,
     // TODO: Add an intermediate struct Post that holds PostType instead
     pub post_type: PostType,
+    /// The anchors of the headlines, which are collected the first time they are needed
+    anchors: Mutex<Option<Arc<Vec<Anchor>>>>,
 }
 
 pub trait BlogElement {
@@ -167,11 +426,17 @@ pub trait BlogElement {
 struct ProjectBuilder {
     posts: HashMap<PathBuf, OrgFile>,
     projects: HashMap<String, Project>,
+    /// The projects that aren't part of this build, e.g. because they are scheduled
+    excluded: HashSet<String>,
 }
 
 impl Website {
-    pub fn load<TMode: Mode>(path: &str) -> Result<Self, WebsiteError> {
+    /// Load the website at `path`. Files published after `today` are scheduled.
+    /// Without a date, the current date in the timezone of the website is used
+    pub fn load<TMode: Mode>(path: &str, today: Option<NaiveDate>) -> Result<Self, WebsiteError> {
         let path = Path::new(path);
+        let config = SiteConfig::load(path)?;
+        let today = today.unwrap_or_else(|| Utc::now().with_timezone(&config.timezone()).date_naive());
 
         let mut project_builder = ProjectBuilder::default();
 
@@ -182,7 +447,7 @@ impl Website {
             let filename = path.file_name().unwrap().to_str().unwrap();
 
             if OrgFile::is_org_file(&path) {
-                let org = OrgFile::load(&path, PostType::Page)
+                let org = OrgFile::load(&path, PostType::Page, today)
                     .or_else(|err| Err(WebsiteError::Page((&path).into(), err)))?;
                 if path.file_name().unwrap() == "index.org" {
                     index = Some(org);
@@ -192,20 +457,152 @@ impl Website {
                     pages.insert(org.path.clone(), org);
                 }
             } else if path.is_dir() {
-                project_builder.process_folder::<TMode>(&filename, &path)?;
+                project_builder.process_folder::<TMode>(&filename, &path, today)?;
             }
         }
 
-        let index = index.expect("Found no website index (index.org in root directiory)");
+        let index = index.ok_or_else(|| WebsiteError::MissingIndex(path.to_path_buf()))?;
 
-        Ok(Website {
-            projects: project_builder.projects(index.from_preamble("default_project").unwrap_or("blog"))?,
+        let mut website = Website {
+            projects: project_builder.projects(default_project(&index))?,
             pages,
-            index
-        })
+            index,
+            config,
+            aliases: BTreeMap::new(),
+        };
+        website.aliases = website.collect_aliases()?;
+        if TMode::reject_conflicts() {
+            if let Some((path, err)) = website.url_errors().into_iter().next() {
+                return Err(WebsiteError::Url(path.to_path_buf(), err));
+            }
+            if let Some((path, err)) = website.tag_errors().into_iter().next() {
+                return Err(WebsiteError::Tag(path.to_path_buf(), err));
+            }
+            if let Some((path, err)) = website.alias_errors().into_iter().next() {
+                return Err(WebsiteError::Alias(path.to_path_buf(), err));
+            }
+        }
+        Ok(website)
     }
 
+    fn collect_aliases(&self) -> Result<BTreeMap<String, PathBuf>, WebsiteError> {
+        let mut files = vec![&self.index];
+        files.extend(self.pages.values());
+        for project in self.projects.values() {
+            files.push(&project.index);
+            files.extend(project.posts.values());
+        }
+
+        let mut aliases = BTreeMap::new();
+        for file in files {
+            let file_aliases = file
+                .aliases()
+                .map_err(|err| WebsiteError::Page(file.path.clone(), err))?;
+            for alias in file_aliases {
+                if aliases.insert(alias.clone(), file.path.clone()).is_some() {
+                    return Err(WebsiteError::DuplicateAlias(alias));
+                }
+            }
+        }
+        Ok(aliases)
+    }
+
+    /// The urls of all pages, project indices and posts relative to the base url, with their files
+    fn file_urls(&self) -> Vec<(String, &Path)> {
+        let mut urls = Vec::new();
+        for page in self.pages.values() {
+            urls.push((page.url(self, String::new()), page.path.as_path()));
+        }
+        for project in self.projects.values() {
+            urls.push((project.url(self, String::new()), project.index.path.as_path()));
+            for post in project.posts.values() {
+                urls.push((post.url(self, String::new()), post.path.as_path()));
+            }
+        }
+        urls
+    }
+
+    /// The files that would be written to the same url as another file, sorted by url and path
+    pub fn url_errors(&self) -> Vec<(&Path, UrlError)> {
+        let mut urls: BTreeMap<String, Vec<&Path>> = BTreeMap::new();
+        for (url, path) in self.file_urls() {
+            urls.entry(url).or_default().push(path);
+        }
+
+        let mut errors = Vec::new();
+        for (url, mut files) in urls.into_iter().filter(|(_, files)| files.len() > 1) {
+            files.sort();
+            for file in files.iter() {
+                let others = files
+                    .iter()
+                    .filter(|other| *other != file)
+                    .map(|other| other.to_path_buf())
+                    .collect();
+                errors.push((
+                    *file,
+                    UrlError {
+                        url: url.clone(),
+                        others,
+                    },
+                ));
+            }
+        }
+        errors
+    }
+
+    /// The aliases that would replace a page or a generated file, with the file declaring them
+    pub fn alias_errors(&self) -> Vec<(&Path, AliasError)> {
+        let urls: HashMap<String, &Path> = self.file_urls().into_iter().collect();
+
+        let mut errors = Vec::new();
+        for (alias, path) in self.aliases.iter() {
+            if let Some(other) = urls.get(alias) {
+                errors.push((
+                    path.as_path(),
+                    AliasError::UsedByFile(alias.clone(), other.to_path_buf()),
+                ));
+            } else if is_reserved_url(alias) {
+                errors.push((path.as_path(), AliasError::Reserved(alias.clone())));
+            }
+        }
+        errors
+    }
+
+    /// Find the file at `path`. If there is none, the file might have been moved,
+    /// so a file with the default url of `path` as alias is returned instead
     pub fn resolve_path(&self, path: &Path) -> Option<&OrgFile> {
+        if let Some(file) = self.find_file(path) {
+            return Some(file);
+        }
+
+        let url = self.default_url(path)?;
+        self.aliases.get(&url).and_then(|path| self.find_file(path))
+    }
+
+    /// The url a file at `path` gets without `#+slug` and publishing date, following the
+    /// same rules as `OrgFile::url`. None if the file isn't part of any page or project
+    fn default_url(&self, path: &Path) -> Option<String> {
+        let root = self.index.path.parent()?;
+        let relative = path.strip_prefix(root).ok()?;
+        let id = path.file_stem()?.to_str()?;
+        let mut components = relative.components();
+        let folder = components.next()?.as_os_str().to_str()?;
+        if components.next().is_none() {
+            return Some(String::from("/") + id);
+        }
+
+        if let Some(project) = self.projects.get(folder) {
+            return project.default_post_url(path);
+        }
+        // posts in folders without index belong to the default project and always use flat urls
+        if root.join(folder).join("index.org").exists() {
+            return None;
+        }
+        let project = self.projects.get(default_project(&self.index))?;
+        Some(format!("/{}/{}", project.slug(), id))
+    }
+
+    fn find_file(&self, path: &Path) -> Option<&OrgFile> {
         if let Some(page) = self.pages.get(path) {
             return Some(page);
         }
@@ -222,7 +619,48 @@ impl Website {
         None
     }
 
-pub fn page_by_id(&self, id: &str) -> Option<&OrgFile> {
+    /// All posts by the slug of their tags, together with the name of the tag.
+    /// Pages and project indices are not included
+    pub fn posts_by_tag(&self) -> BTreeMap<String, (&str, Vec<&OrgFile>)> {
+        let mut tags: BTreeMap<String, (&str, Vec<&OrgFile>)> = BTreeMap::new();
+        for post in sorted_posts(self) {
+            for tag in post.tags.iter() {
+                tags.entry(slugify(tag))
+                    .or_insert_with(|| (tag, Vec::new()))
+                    .1
+                    .push(post);
+            }
+        }
+        tags
+    }
+
+    /// The tags without a slug or with the same slug as another tag, with the file using them
+    pub fn tag_errors(&self) -> Vec<(&Path, TagError)> {
+        let mut errors = Vec::new();
+        let mut slugs: HashMap<String, &str> = HashMap::new();
+        for post in sorted_posts(self) {
+            for tag in post.tags.iter() {
+                let slug = slugify(tag);
+                if slug.is_empty() {
+                    errors.push((post.path.as_path(), TagError::EmptySlug(tag.clone())));
+                    continue;
+                }
+                match slugs.get(&slug) {
+                    Some(other) if other != tag => errors.push((
+                        post.path.as_path(),
+                        TagError::SlugCollision(tag.clone(), other.to_string()),
+                    )),
+                    Some(_) => {}
+                    None => {
+                        slugs.insert(slug, tag);
+                    }
+                }
+            }
+        }
+        errors
+    }
+
+    pub fn page_by_id(&self, id: &str) -> Option<&OrgFile> {
         for page in self.pages.values() {
             if page.id() == id {
                 return Some(&page);
@@ -235,7 +673,9 @@ pub fn page_by_id(&self, id: &str) -> Option<&OrgFile> {
 impl ProjectBuilder {
     fn projects(mut self, default_project: &str) -> Result<HashMap<String, Project>, WebsiteError> {
         match self.projects.get_mut(default_project) {
-            None => Err(WebsiteError::DefaultProjectDoesNotExist),
+            // posts outside of a project belong to the default project, so they are left out as well
+            None if self.excluded.contains(default_project) => Ok(self.projects),
+            None => Err(WebsiteError::DefaultProjectDoesNotExist(default_project.to_string())),
             Some(p) => {
                 p.posts.extend(self.posts);
                 Ok(self.projects)
@@ -243,21 +683,30 @@ impl ProjectBuilder {
         }
     }
 
-    fn process_folder<TMode: Mode>(&mut self, name: &str, path: &Path) -> Result<(), WebsiteError> {
+    fn process_folder<TMode: Mode>(
+        &mut self,
+        name: &str,
+        path: &Path,
+        today: NaiveDate,
+    ) -> Result<(), WebsiteError> {
         let mut index = path.to_path_buf();
         index.push("index.org");
         if index.exists() {
-            self.projects.insert(
-                name.to_string(),
-                Project::load::<TMode>(name, path)
-                    .or_else(|err| Err(WebsiteError::Project(name.to_string(), err)))?,
-            );
+            let project = Project::load::<TMode>(name, path, today)
+                .map_err(|err| WebsiteError::Project(name.to_string(), err))?;
+            if TMode::include_project(&project)
+                .map_err(|err| WebsiteError::Project(name.to_string(), err))?
+            {
+                self.projects.insert(name.to_string(), project);
+            } else {
+                self.excluded.insert(name.to_string());
+            }
         } else {
             for file in find_all_project_files(path)?.iter() {
                 // TODO:
                 // Setting the post type here doesn't work if the default project is a multi part project
                 // In which the type should be PostType::Mini
-                let org = OrgFile::load(&file, PostType::Normal)
+                let org = OrgFile::load(&file, PostType::Normal, today)
                     .or_else(|err| Err(WebsiteError::Page(file.into(), err)))?;
 
                 if TMode::include_post(&org)
@@ -271,6 +720,59 @@ impl ProjectBuilder {
     }
 }
 
+/// Turn a string into a lowercase identifier that can be used in urls and html ids
+pub fn slugify(value: &str) -> String {
+    let mut slug = String::new();
+    for c in value.trim().chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+    slug
+}
+
+/// Whether `url` is used by a page or file that silas generates in addition to the org files
+fn is_reserved_url(url: &str) -> bool {
+    let generated_files = [
+        FeedFormat::RSS.file_name(),
+        FeedFormat::Atom.file_name(),
+        FeedFormat::JSON.file_name(),
+        RedirectFormat::Netlify.file_name(),
+        RedirectFormat::Nginx.file_name(),
+        SITEMAP_FILE_NAME,
+        ROBOTS_FILE_NAME,
+        SEARCH_INDEX_FILE_NAME,
+        CACHE_FILE_NAME,
+    ];
+    let first_segment = url.trim_start_matches('/').split('/').next().unwrap_or_default();
+    // the tag listings are below /tags, all other generated files are in the root folder
+    first_segment == "tags"
+        || url == "/search"
+        || generated_files.iter().any(|file| url.trim_start_matches('/') == *file)
+}
+
+/// The posts of all projects, sorted by their path so that the name of a tag and the
+/// reported errors don't depend on the order of the files in the hash maps
+fn sorted_posts(website: &Website) -> Vec<&OrgFile> {
+    let mut posts: Vec<&OrgFile> = website
+        .projects
+        .values()
+        .flat_map(|project| project.posts.values())
+        .collect();
+    posts.sort_by(|lhs, rhs| lhs.path.cmp(&rhs.path));
+    posts
+}
+
+/// The url of the listing page for a tag, relative to the base url
+pub fn tag_url(tag: &str) -> String {
+    String::from("/tags/") + &slugify(tag)
+}
+
 fn find_all_project_files(path: &Path) -> Result<Vec<PathBuf>, IOError> {
     let mut files = Vec::new();
     let mut folders = Vec::new();
@@ -291,11 +793,26 @@ fn find_all_project_files(path: &Path) -> Result<Vec<PathBuf>, IOError> {
     Ok(files)
 }
 
+/// The id of the project that contains the posts outside of project folders
+fn default_project(index: &OrgFile) -> &str {
+    index.from_preamble("default_project").unwrap_or("blog")
+}
+
+/// The path of `file` relative to the project folder `root`, without the file extension
+fn nested_id(root: &Path, file: &Path) -> String {
+    let relative = file.strip_prefix(root).unwrap().with_extension("");
+    relative
+        .components()
+        .map(|part| part.as_os_str().to_str().unwrap())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
 impl Project {
-    fn load<TMode: Mode>(id: &str, path: &Path) -> Result<Self, ProjectError> {
+    fn load<TMode: Mode>(id: &str, path: &Path, today: NaiveDate) -> Result<Self, ProjectError> {
         let mut index = path.to_path_buf();
         index.push("index.org");
-        let index = OrgFile::load(&index, PostType::Index);
+        let index = OrgFile::load(&index, PostType::Index, today);
         if index.is_err() {
             return Err(ProjectError::OrgFile(
                 path.to_str().unwrap().to_string() + "/index.org",
@@ -304,12 +821,30 @@ impl Project {
         }
         let index = index.unwrap();
 
-        let project_type = ProjectType::from_str(index.from_preamble("type"));
-
-        if project_type.is_err() {
-            return Err(ProjectError::UnknownProjectType);
+        let project_type = ProjectType::from_str(index.from_preamble("type")).or_else(|_| {
+            Err(ProjectError::UnknownProjectType(
+                index.from_preamble("type").unwrap_or_default().to_string(),
+            ))
+        })?;
+        OrgFileError::to_project_error(index.parse_from_preamble::<PostOrder>("order"), &index.path)?;
+        let url_style = OrgFileError::to_project_error(
+            index.parse_from_preamble::<UrlStyle>("url_style"),
+            &index.path,
+        )?
+        .unwrap_or_default();
+        let permalink = index.from_preamble("permalink").map(|p| p.trim().to_string());
+        if let Some(pattern) = &permalink {
+            if !is_valid_permalink(pattern) {
+                return Err(ProjectError::OrgFile(
+                    index.path.to_str().unwrap().to_string(),
+                    OrgFileError::InvalidField {
+                        key: "permalink".to_string(),
+                        value: pattern.clone(),
+                        line: keyword_line(&index.contents, "permalink"),
+                    },
+                ));
+            }
         }
-        let project_type = project_type.unwrap();
 
         let post_type = if project_type == ProjectType::MultiPart {
             PostType::Mini
@@ -319,12 +854,15 @@ impl Project {
 
         let mut posts = HashMap::new();
         let mut ids = HashSet::new();
-        for path in find_all_project_files(path)?.iter() {
-            let org = OrgFileError::to_project_error(OrgFile::load(path, post_type), path)?;
-            if ids.contains(org.id()) {
+        let root = path;
+        for path in find_all_project_files(root)?.iter() {
+            let mut org = OrgFileError::to_project_error(OrgFile::load(path, post_type, today), path)?;
+            if url_style == UrlStyle::Nested {
+                org.id = nested_id(root, path);
+            }
+            if !ids.insert(org.id().to_string()) && TMode::reject_conflicts() {
                 return Err(ProjectError::DuplicateFileID(org.id().to_string()));
             }
-            ids.insert(org.id().to_string());
 
             if OrgFileError::to_project_error(TMode::include_post(&org), path)? {
                 posts.insert(org.path.clone(), org);
@@ -336,6 +874,8 @@ impl Project {
             project_type,
             posts,
             index,
+            permalink,
+            url_style,
         })
     }
 
@@ -343,8 +883,43 @@ impl Project {
         &self.id
     }
 
+    /// The url segment of the project, which is `#+slug` from the index or the folder name
+    pub fn slug(&self) -> &str {
+        self.index.from_preamble("slug").map_or(&self.id, |slug| slug.trim_matches('/'))
+    }
+
+    /// The url of a post relative to the base url. Posts without a publishing date
+    /// don't use the permalink pattern
+    fn post_url(&self, post: &OrgFile) -> String {
+        match (&self.permalink, post.published) {
+            (Some(pattern), Some(date)) => pattern
+                .split('/')
+                .map(|segment| match segment {
+                    ":project" => self.slug().to_string(),
+                    ":year" => date.format("%Y").to_string(),
+                    ":month" => date.format("%m").to_string(),
+                    ":day" => date.format("%d").to_string(),
+                    ":slug" => post.slug().to_string(),
+                    segment => segment.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("/"),
+            _ => format!("/{}/{}", self.slug(), post.slug()),
+        }
+    }
+
+    /// The url of a post at `path` without `#+slug` and publishing date, relative to the base url
+    fn default_post_url(&self, path: &Path) -> Option<String> {
+        let id = match self.url_style {
+            UrlStyle::Nested => nested_id(self.index.path.parent()?, path),
+            UrlStyle::Flat => path.file_stem()?.to_str()?.to_string(),
+        };
+        Some(format!("/{}/{}", self.slug(), id))
+    }
+
+    /// Whether the index has a publishing date that isn't in the future
     pub fn published(&self) -> bool {
-        self.index.published.is_some()
+        self.index.published.is_some() && !self.index.scheduled
     }
 }
 
@@ -353,7 +928,7 @@ impl OrgFile {
         path.is_file() && path.extension().map_or(false, |ext| ext == "org")
     }
 
-    fn load(path: &PathBuf, post_type: PostType) -> Result<Self, OrgFileError> {
+    fn load(path: &PathBuf, post_type: PostType, today: NaiveDate) -> Result<Self, OrgFileError> {
         assert!(
             OrgFile::is_org_file(path),
             "Trying to load {:?} as orgfile. This shouldn't happen",
@@ -361,29 +936,74 @@ impl OrgFile {
         );
 
         let contents = String::from_utf8(fs::read(path)?)?;
-        let parser = Org::parse(&contents);
+        // only the preamble is needed here. The full file is parsed when rendering it
+        let parser = Org::parse(OrgFile::preamble_source(&contents));
 
         let preamble = OrgFile::extract_preamble(&parser, path);
-        let published = match preamble.get("published") {
-            None => None,
-            Some(d) => Some(OrgFile::parse_date(&d)?),
-        };
-        let last_edit = match preamble.get("last-edit") {
-            None => None,
-            Some(d) => Some(OrgFile::parse_date(&d)?),
-        };
+        let published = OrgFile::parse_date(&preamble, &contents, "published")?;
+        let last_edit = OrgFile::parse_date(&preamble, &contents, "last-edit")?;
+        let tags = OrgFile::parse_tags(&preamble);
+        // the slug is part of the url, so it mustn't leave the folder of the project
+        if let Some(slug) = preamble.get("slug") {
+            if has_dot_segments(slug) || slug.trim_matches('/').is_empty() {
+                return Err(OrgFileError::InvalidField {
+                    key: String::from("slug"),
+                    value: slug.clone(),
+                    line: keyword_line(&contents, "slug"),
+                });
+            }
+        }
 
         Ok(OrgFile {
             id: path.file_stem().unwrap().to_str().unwrap().to_string(),
             path: path.clone(),
             contents,
             preamble,
+            scheduled: published.map_or(false, |date| date > today),
             published,
             last_edit,
+            tags,
             post_type,
+            anchors: Mutex::new(None),
         })
     }
 
+    /// The anchors of all headlines. The file is only parsed for them once, since every link
+    /// to one of its headlines needs them
+    pub fn anchors(&self) -> Arc<Vec<Anchor>> {
+        self.anchors
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| Arc::new(headline_anchors(&self.contents)))
+            .clone()
+    }
+
+    /// Check the fields every file needs. This is done when rendering the file instead of
+    /// when loading it, so that `silas check` can report all files with missing fields
+    pub fn check_required_fields(&self) -> Result<(), OrgFileError> {
+        if self.from_preamble("title").is_none() {
+            return Err(OrgFileError::MissingRequiredField("title"));
+        }
+        // project indices are listed with their summary
+        if self.post_type == PostType::Index && self.from_preamble("summary").is_none() {
+            return Err(OrgFileError::MissingRequiredField("summary"));
+        }
+        Ok(())
+    }
+
+    /// The leading keyword lines of an org file, which contain the complete preamble
+    fn preamble_source(contents: &str) -> &str {
+        let mut end = 0;
+        for line in contents.split_inclusive('\n') {
+            let trimmed = line.trim().to_lowercase();
+            if !trimmed.is_empty() && (!trimmed.starts_with("#+") || trimmed.starts_with("#+begin")) {
+                break;
+            }
+            end += line.len();
+        }
+        &contents[..end]
+    }
+
     fn extract_preamble(org: &Org, filename: &Path) -> HashMap<String, String> {
         let mut iter = org.iter();
         iter.next(); // Start document
@@ -410,36 +1030,103 @@ impl OrgFile {
         preamble
     }
 
-    fn parse_date(date_str: &str) -> chrono::ParseResult<chrono::naive::NaiveDate> {
-        chrono::naive::NaiveDate::parse_from_str(date_str, "<%Y-%m-%d>")
+    /// Tags can be separated by colons (as in `#+filetags: :a:b:`), commas or whitespace
+    fn parse_tags(preamble: &HashMap<String, String>) -> Vec<String> {
+        let mut tags: Vec<String> = Vec::new();
+        for key in ["tags", "filetags"].iter() {
+            if let Some(value) = preamble.get(*key) {
+                for tag in value.split(|c: char| c == ':' || c == ',' || c.is_whitespace()) {
+                    let tag = tag.to_lowercase();
+                    if !tag.is_empty() && !tags.contains(&tag) {
+                        tags.push(tag);
+                    }
+                }
+            }
+        }
+        tags
+    }
+
+    fn parse_date(
+        preamble: &HashMap<String, String>,
+        contents: &str,
+        key: &'static str,
+    ) -> Result<Option<chrono::naive::NaiveDate>, OrgFileError> {
+        match preamble.get(key) {
+            None => Ok(None),
+            Some(date) => chrono::naive::NaiveDate::parse_from_str(date, "<%Y-%m-%d>")
+                .map(Some)
+                .or_else(|err| {
+                    Err(OrgFileError::Date {
+                        key,
+                        line: keyword_line(contents, key),
+                        err,
+                    })
+                }),
+        }
     }
 
     pub fn id(&self) -> &str {
         &self.id
     }
 
+    /// The old urls of the file from `#+aliases`, relative to the base url and without trailing slashes.
+    /// Redirect pages are written to the aliases, so they can't contain `.` or `..` segments
+    pub fn aliases(&self) -> Result<Vec<String>, OrgFileError> {
+        let mut aliases = Vec::new();
+        for alias in self.from_preamble("aliases").unwrap_or_default().split_whitespace() {
+            if has_dot_segments(alias) {
+                return Err(OrgFileError::InvalidField {
+                    key: String::from("aliases"),
+                    value: alias.to_string(),
+                    line: keyword_line(&self.contents, "aliases"),
+                });
+            }
+            let alias = String::from("/") + alias.trim_matches('/');
+            if alias != "/" {
+                aliases.push(alias);
+            }
+        }
+        Ok(aliases)
+    }
+
+    /// The last segment of the url, which is `#+slug` or the id otherwise
+    pub fn slug(&self) -> &str {
+        self.from_preamble("slug").map_or(&self.id, |slug| slug.trim_matches('/'))
+    }
+
     pub fn from_preamble<'a>(&'a self, key: &str) -> Option<&'a str> {
         return self.preamble.get(key).and_then(|s| Some(s.as_str()));
     }
 
-    pub fn parse_from_preamble<T: std::str::FromStr + std::fmt::Debug>(
+    pub fn parse_from_preamble<T: std::str::FromStr>(
         &self,
         key: &str,
-    ) -> Option<T>
-    where
-        T::Err: std::fmt::Debug,
-    {
-        self.from_preamble(key).map(|val| {
-            let res = val.parse::<T>();
-            assert!(
-                res.is_ok(),
-                "Unable to convert `{}` to {} in preamble from {:?}",
-                val,
-                std::any::type_name::<T>(),
-                self.path
-            );
-            res.unwrap()
-        })
+    ) -> Result<Option<T>, OrgFileError> {
+        match self.from_preamble(key) {
+            None => Ok(None),
+            Some(value) => value.parse::<T>().map(Some).or_else(|_| {
+                Err(OrgFileError::InvalidField {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                    line: keyword_line(&self.contents, key),
+                })
+            }),
+        }
+    }
+
+    /// The depth of the table of contents from `#+options: toc:N`. `toc:t` includes all headlines.
+    /// Returns None if the option is missing or `toc:nil`
+    pub fn toc_depth(&self) -> Option<usize> {
+        let options = self.from_preamble("options")?;
+        let value = options
+            .split_whitespace()
+            .filter_map(|option| option.strip_prefix("toc:"))
+            .last()?;
+        match value {
+            "nil" => None,
+            "t" => Some(usize::MAX),
+            depth => depth.parse::<usize>().ok().filter(|depth| *depth > 0),
+        }
     }
 
     pub fn resolve_link(&self, link: &str) -> PathBuf {
@@ -476,7 +1163,7 @@ impl BlogElement for Website {
 
 impl BlogElement for Project {
     fn url(&self, _website: &Website, base: String) -> String {
-        base + "/" + self.id()
+        base + "/" + self.slug()
     }
 
     fn title(&self) -> &str {
@@ -495,38 +1182,81 @@ impl BlogElement for OrgFile {
         }
 
         if website.pages.contains_key(&self.path) {
-            return base + "/" + self.id();
+            return base + "/" + self.slug();
         }
 
         for proj in website.projects.values() {
             if proj.index.path == self.path {
-                return base + "/" + proj.id();
+                return base + "/" + proj.slug();
             }
 
             if proj.posts.contains_key(&self.path) {
-                return base + "/" + proj.id() + "/" + self.id();
+                return base + &proj.post_url(self);
             }
         }
         panic!("OrgFile:url called on element not loaded by given website");
     }
 
+    /// Files without title fail to render, see `check_required_fields`
     fn title(&self) -> &str {
-        let title = &self.from_preamble("title");
-        assert!(
-            title.is_some(),
-            "Orgfile {:?} is missing a title",
-            self.path
-        );
-        title.unwrap()
+        self.from_preamble("title").unwrap_or_default()
     }
 
     fn description(&self) -> &str {
-        let summary = self.from_preamble("summary");
-        assert!(
-            summary.is_some(),
-            "Orgfile {:?} is missing a summary",
-            self.path
-        );
-        summary.unwrap()
+        self.from_preamble("summary").unwrap_or_default()
     }
 }
+
+#[test]
+fn test_slugify() {
+    assert_eq!(slugify("Hello, World!"), "hello-world");
+    assert_eq!(slugify("  C++ & Rust "), "c-rust");
+    assert_eq!(slugify("c++"), slugify("c#"));
+    assert_eq!(slugify("+++"), "");
+}
+
+#[test]
+fn test_preamble_source() {
+    let contents = "#+title: Test\n#+TAGS: a b\n\n#+begin_src rust\nfn main() {}\n#+end_src\n";
+    assert_eq!(OrgFile::preamble_source(contents), "#+title: Test\n#+TAGS: a b\n\n");
+    assert_eq!(OrgFile::preamble_source("Some text\n#+title: Test"), "");
+}
+
+#[test]
+fn test_resolve_moved_file() -> Result<(), WebsiteError> {
+    let website = Website::load::<super::PreviewMode>("testsite", None)?;
+    let moved = website.resolve_path(Path::new("testsite/default/2021/old-name.org"));
+    assert_eq!(moved.map(|file| file.id()), Some("renamed"));
+    assert!(website
+        .resolve_path(Path::new("testsite/default/old-name.org"))
+        .is_some());
+    assert!(website
+        .resolve_path(Path::new("testsite/default/2021/other.org"))
+        .is_none());
+    Ok(())
+}
+
+#[test]
+fn test_nested_id() {
+    let root = Path::new("blog/archive");
+    assert_eq!(nested_id(root, Path::new("blog/archive/2021/foo.org")), "2021/foo");
+    assert_eq!(nested_id(root, Path::new("blog/archive/foo.org")), "foo");
+}
+
+#[test]
+fn test_permalink() {
+    assert!(is_valid_permalink("/:year/:month/:slug"));
+    assert!(is_valid_permalink("/:project/:year/:slug"));
+    assert!(!is_valid_permalink("/:year/:month"));
+    assert!(!is_valid_permalink("/:year/:week/:slug"));
+    assert!(!is_valid_permalink(":project/:slug"));
+    assert!(!is_valid_permalink("/../:year/:slug"));
+}
+
+#[test]
+fn test_dot_segments() {
+    assert!(has_dot_segments("../../etc"));
+    assert!(has_dot_segments("a/../../b"));
+    assert!(has_dot_segments("./a"));
+    assert!(!has_dot_segments("a/b..c/.d"));
+}
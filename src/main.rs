@@ -1,50 +1,94 @@
 mod builder;
+mod serve;
 use builder::{Builder, Mode, PreviewMode, ReleaseMode};
 
-fn execute<T: Mode>(matches: &clap::ArgMatches) -> Result<(), std::io::Error> {
+/// Print an error and exit with a non-zero code
+fn fail(context: &str, err: &dyn std::error::Error) -> ! {
+    eprintln!("Error: {}: {}", context, err);
+    std::process::exit(1);
+}
+
+fn execute<T: Mode>(matches: &clap::ArgMatches) {
     let builder = match Builder::new::<T>(
         matches.value_of("PATH").unwrap(),
         matches.value_of("theme").unwrap(),
         matches.value_of("output").unwrap(),
+        as_of(matches),
     ) {
-        Err(err) => panic!("Unable to instantiate builder: {:?}", err),
+        Err(err) => fail("Unable to instantiate builder", &err),
         Ok(builder) => builder,
     };
 
-    match if matches.is_present("file") {
-        builder.generate_single_file::<T>(matches.value_of("file").unwrap())
+    let result = if matches.is_present("file") {
+        builder
+            .generate_single_file::<T>(matches.value_of("file").unwrap())
+            .map(|_| None)
     } else {
-        builder.generate::<T>()
-    } {
+        builder.generate::<T>().map(Some)
+    };
+    // exiting skips the destructors, so the builder has to be dropped to remove its build folder
+    let report = match result {
         Err(err) => {
-            builder.clear_generated_files();
-            panic!("Generation failed with `{:?}``", err);
+            drop(builder);
+            fail("Generation failed", &err);
         }
-        Ok(()) => {
-            println!("Generation successful!");
-            builder.copy_generated_files()
+        Ok(report) => report,
+    };
+    if let Err(err) = builder.copy_generated_files() {
+        drop(builder);
+        fail("Unable to copy generated files", &err);
+    }
+    match report {
+        Some(report) if !report.is_empty() => {
+            println!("Generation finished with errors: {}", report)
         }
+        _ => println!("Generation successful!"),
     }
 }
 
-fn main() -> Result<(), std::io::Error> {
+fn path_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("PATH")
+        .help("The path to the blog folder")
+        .required(true)
+}
+
+fn theme_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("theme")
+        .long("theme")
+        .help("The path to the theme directory")
+        .required(true)
+        .default_value("theme")
+        .takes_value(true)
+}
+
+fn as_of_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("as-of")
+        .long("as-of")
+        .alias("now")
+        .help("Use this date (YYYY-MM-DD) instead of today to decide which posts are published")
+        .takes_value(true)
+        .validator(|date| {
+            chrono::NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        })
+}
+
+/// The date passed with `--as-of`. The validator ensures that it is valid
+fn as_of(matches: &clap::ArgMatches) -> Option<chrono::NaiveDate> {
+    matches
+        .value_of("as-of")
+        .map(|date| chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap())
+}
+
+fn main() {
     let matches = clap::App::new("Silas")
         .version("0.1")
         .author("Johannes Huwald <hey@jhuwald.com>")
         .about("The SSG for my blog at jhuwald.com")
-        .arg(
-            clap::Arg::with_name("PATH")
-                .help("The path to the blog folder")
-                .required(true),
-        )
-        .arg(
-            clap::Arg::with_name("theme")
-                .long("theme")
-                .help("The path to the theme directory")
-                .required(true)
-                .default_value("theme")
-                .takes_value(true),
-        )
+        .setting(clap::AppSettings::SubcommandsNegateReqs)
+        .arg(path_arg())
+        .arg(theme_arg())
         .arg(
             clap::Arg::with_name("file")
                 .long("file")
@@ -69,12 +113,96 @@ fn main() -> Result<(), std::io::Error> {
                 .help("The output directory for the SSG")
                 .required(true),
         )
+        .arg(as_of_arg())
+        .subcommand(
+            clap::SubCommand::with_name("serve")
+                .about("Serve the blog locally and rebuild it on changes")
+                .arg(path_arg())
+                .arg(theme_arg())
+                .arg(
+                    clap::Arg::with_name("port")
+                        .long("port")
+                        .short("p")
+                        .help("The port of the development server")
+                        .default_value("8000")
+                        .takes_value(true)
+                        .validator(|port| port.parse::<u16>().map(|_| ()).map_err(|err| err.to_string())),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("check")
+                .about("Check the blog for broken links and other problems without generating it")
+                .arg(path_arg())
+                .arg(
+                    clap::Arg::with_name("json")
+                        .long("json")
+                        .help("Print the problems as json")
+                        .takes_value(false),
+                )
+                .arg(
+                    clap::Arg::with_name("external")
+                        .long("external")
+                        .help("Also check that all http(s) links are valid urls")
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("schedule")
+                .about("List all pages and posts with a publishing date in the future")
+                .arg(path_arg())
+                .arg(as_of_arg()),
+        )
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("serve") {
+        if let Err(err) = serve::serve(
+            matches.value_of("PATH").unwrap(),
+            matches.value_of("theme").unwrap(),
+            matches.value_of("port").unwrap().parse().unwrap(),
+        ) {
+            fail("Development server failed", &err);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("check") {
+        let report = builder::check(
+            matches.value_of("PATH").unwrap(),
+            matches.is_present("external"),
+        );
+        if matches.is_present("json") {
+            match report.to_json() {
+                Ok(json) => println!("{}", json),
+                Err(err) => fail("Unable to serialize report", &err),
+            }
+        } else {
+            report.print();
+        }
+        if !report.is_empty() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("schedule") {
+        let scheduled = match builder::schedule(matches.value_of("PATH").unwrap(), as_of(matches)) {
+            Err(err) => fail("Unable to load the blog", &err),
+            Ok(scheduled) => scheduled,
+        };
+        for file in scheduled.iter() {
+            println!("{}  {}  {}", file.published, file.path.display(), file.title);
+        }
+        match scheduled.len() {
+            0 => println!("No scheduled posts"),
+            1 => println!("1 scheduled post"),
+            n => println!("{} scheduled posts", n),
+        }
+        return;
+    }
+
     if matches.is_present("preview") {
-        execute::<PreviewMode>(&matches)?
+        execute::<PreviewMode>(&matches)
     } else {
-        execute::<ReleaseMode>(&matches)?
+        execute::<ReleaseMode>(&matches)
     };
-    Ok(())
 }
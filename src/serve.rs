@@ -0,0 +1,237 @@
+use std::fs::File;
+use std::io::Error as IOError;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use tiny_http::{Header, Request, Response, Server};
+
+use crate::builder;
+use crate::builder::{Builder, ServeMode};
+
+/// Polls the server for the current build version and reloads the page once it changes
+const LIVE_RELOAD_SCRIPT: &str = r#"(function () {
+  var version = null;
+  setInterval(function () {
+    fetch("/__silas/version")
+      .then(function (response) { return response.text(); })
+      .then(function (current) {
+        if (version !== null && current !== version) {
+          window.location.reload();
+        }
+        version = current;
+      })
+      .catch(function () {});
+  }, 1000);
+})();
+"#;
+
+#[derive(Debug)]
+pub enum Error {
+    IO(IOError),
+    Server(Box<dyn std::error::Error + Send + Sync>),
+    Watch(notify::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::IO(err) => write!(f, "{}", err),
+            Self::Server(err) => write!(f, "Unable to start the server: {}", err),
+            Self::Watch(err) => write!(f, "Unable to watch for changes: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<IOError> for Error {
+    fn from(err: IOError) -> Self {
+        Self::IO(err)
+    }
+}
+
+impl From<notify::Error> for Error {
+    fn from(err: notify::Error) -> Self {
+        Self::Watch(err)
+    }
+}
+
+/// Build the website, serve it on `port` and rebuild it whenever a file
+/// in the blog folder, the theme or one of its parents changes
+pub fn serve(website_path: &str, theme_path: &str, port: u16) -> Result<(), Error> {
+    let url = format!("http://localhost:{}", port);
+    let mut output = std::env::temp_dir();
+    output.push(format!("silas-serve-{}", port));
+    let output_path = output.to_str().unwrap().to_string();
+
+    let version = Arc::new(AtomicUsize::new(0));
+    rebuild(website_path, theme_path, &output_path, &url);
+
+    let server = Server::http(("127.0.0.1", port)).map_err(Error::Server)?;
+    {
+        let version = version.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                if let Err(err) = respond(request, &output, &version) {
+                    println!("Warning: Unable to answer request: {}", err);
+                }
+            }
+        });
+    }
+    println!("Serving website on {}", url);
+
+    let (tx, rx) = channel();
+    let mut watcher = notify::watcher(tx, Duration::from_millis(300))?;
+    watcher.watch(website_path, RecursiveMode::Recursive)?;
+    // parent themes can be outside of the theme folder. If the chain can't be resolved,
+    // the rebuild already reported the error
+    let theme_folders =
+        builder::theme_folders(theme_path).unwrap_or_else(|_| vec![PathBuf::from(theme_path)]);
+    for folder in theme_folders {
+        watcher.watch(folder, RecursiveMode::Recursive)?;
+    }
+
+    for event in rx.iter() {
+        match event {
+            DebouncedEvent::NoticeWrite(_)
+            | DebouncedEvent::NoticeRemove(_)
+            | DebouncedEvent::Chmod(_) => continue,
+            DebouncedEvent::Error(err, path) => {
+                println!("Warning: Error while watching {:?}: {:?}", path, err);
+                continue;
+            }
+            _ => {}
+        }
+
+        if rebuild(website_path, theme_path, &output_path, &url) {
+            version.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    Ok(())
+}
+
+/// Generate the website into `output_path`. Returns false if the generation failed
+fn rebuild(website_path: &str, theme_path: &str, output_path: &str, url: &str) -> bool {
+    let builder = match Builder::serve(website_path, theme_path, output_path, url.to_string()) {
+        Err(err) => {
+            println!("Unable to instantiate builder: {}", err);
+            return false;
+        }
+        Ok(builder) => builder,
+    };
+
+    match builder.generate::<ServeMode>() {
+        Err(err) => {
+            println!("Generation failed: {}", err);
+            false
+        }
+        Ok(report) => match builder.copy_generated_files() {
+            Err(err) => {
+                println!("Unable to copy generated files: {}", err);
+                false
+            }
+            Ok(()) => {
+                if report.is_empty() {
+                    println!("Generation successful!");
+                } else {
+                    println!("Generation finished with errors: {}", report);
+                }
+                true
+            }
+        },
+    }
+}
+
+fn respond(request: Request, root: &Path, version: &AtomicUsize) -> Result<(), IOError> {
+    let url = request
+        .url()
+        .split(|c| c == '?' || c == '#')
+        .next()
+        .unwrap()
+        .to_string();
+
+    match url.as_str() {
+        "/__silas/live-reload.js" => request.respond(
+            Response::from_string(LIVE_RELOAD_SCRIPT)
+                .with_header(content_type("application/javascript")),
+        ),
+        "/__silas/version" => {
+            request.respond(Response::from_string(version.load(Ordering::SeqCst).to_string()))
+        }
+        url => match resolve_file(root, url) {
+            None => request.respond(Response::from_string("Not found").with_status_code(404)),
+            Some(path) => {
+                let mime = mime_type(&path);
+                request.respond(Response::from_file(File::open(path)?).with_header(content_type(mime)))
+            }
+        },
+    }
+}
+
+fn content_type(mime: &str) -> Header {
+    Header::from_bytes(&b"Content-Type"[..], mime.as_bytes()).unwrap()
+}
+
+/// Map the url of a request to a file in the output folder
+fn resolve_file(root: &Path, url: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for part in percent_decode(url)?.split('/') {
+        match part {
+            "" | "." => {}
+            // never serve anything outside of the output folder
+            ".." => return None,
+            part => path.push(part),
+        }
+    }
+
+    if path.is_dir() {
+        path.push("index.html");
+    }
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn percent_decode(url: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(url.len());
+    let mut iter = url.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css",
+        Some("js") => "application/javascript",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        // the rss feeds are written without extension
+        None if path.file_name().map_or(false, |name| name == "feed") => "application/rss+xml",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,61 @@
+// Client side search over the search index written by silas
+(function () {
+    var script = document.currentScript;
+    var input = document.getElementById("search-input");
+    var results = document.getElementById("search-results");
+    var entries = [];
+
+    function excerpt(text, word) {
+        var index = text.toLowerCase().indexOf(word);
+        if (index < 0) {
+            return text.slice(0, 160);
+        }
+        var start = Math.max(0, index - 60);
+        return (start > 0 ? "…" : "") + text.slice(start, start + 160) + "…";
+    }
+
+    function matches(entry, words) {
+        var haystack = [entry.title, entry.subtitle, entry.summary, entry.text]
+            .join(" ")
+            .toLowerCase();
+        return words.every(function (word) {
+            return haystack.indexOf(word) >= 0;
+        });
+    }
+
+    function render() {
+        var words = input.value.toLowerCase().split(/\s+/).filter(Boolean);
+        results.innerHTML = "";
+        if (words.length === 0) {
+            return;
+        }
+        entries
+            .filter(function (entry) {
+                return matches(entry, words);
+            })
+            .forEach(function (entry) {
+                var item = document.createElement("li");
+                var link = document.createElement("a");
+                link.href = entry.url;
+                link.textContent = entry.title;
+                var text = document.createElement("p");
+                text.textContent = excerpt(entry.text, words[0]);
+                item.appendChild(link);
+                item.appendChild(text);
+                results.appendChild(item);
+            });
+        if (!results.firstChild) {
+            results.innerHTML = "<li>No results</li>";
+        }
+    }
+
+    fetch(script.dataset.index)
+        .then(function (response) {
+            return response.json();
+        })
+        .then(function (index) {
+            entries = index;
+            render();
+        });
+    input.addEventListener("input", render);
+})();